@@ -0,0 +1,79 @@
+#![feature(test)]
+
+extern crate test;
+extern crate shady_script;
+
+use test::Bencher;
+
+use shady_script::{Inputs, Value, BATCH};
+
+static SCRIPT: &'static str = r#"
+image {
+    a = sin(x * 10 + t) * 0.5 + 0.5;
+    b = cos(y * 10 - t) * 0.5 + 0.5;
+    c = (a, b, max(a, b));
+    if x < mx {
+        c = c * 0.5;
+    } else {
+        if y > my {
+            return (b, a, min(a, b));
+        };
+    };
+    c
+}
+"#;
+
+const SIZE: usize = 64;
+
+fn inputs(i: usize) -> Inputs {
+    Inputs {
+        x: (i % SIZE) as f32 / SIZE as f32,
+        y: (i / SIZE) as f32 / SIZE as f32,
+        t: 1.5,
+        mx: 0.5,
+        my: 0.25,
+    }
+}
+
+#[bench]
+fn interpreter(b: &mut Bencher) {
+    let sdy = shady_script::parse_input(SCRIPT).unwrap().analyse().unwrap();
+    sdy.with_images(|image| b.iter(|| {
+        for i in 0..SIZE * SIZE {
+            test::black_box(image.eval(&inputs(i)));
+        }
+    }));
+}
+
+#[bench]
+fn bytecode(b: &mut Bencher) {
+    let sdy = shady_script::parse_input(SCRIPT).unwrap().analyse().unwrap();
+    sdy.with_images(|image| {
+        let program = image.program();
+        let mut machine = program.machine();
+
+        b.iter(|| {
+            for i in 0..SIZE * SIZE {
+                test::black_box(machine.eval(&inputs(i)));
+            }
+        })
+    });
+}
+
+#[bench]
+fn batched(b: &mut Bencher) {
+    let sdy = shady_script::parse_input(SCRIPT).unwrap().analyse().unwrap();
+    sdy.with_images(|image| {
+        let program = image.program();
+        let mut machine = program.batch_machine();
+        let mut out = [Value::Void; BATCH];
+        let inputs: Vec<Inputs> = (0..SIZE * SIZE).map(inputs).collect();
+
+        b.iter(|| {
+            for packet in inputs.chunks(BATCH) {
+                machine.eval(packet, &mut out);
+                test::black_box(&out);
+            }
+        })
+    });
+}
@@ -3,106 +3,315 @@ use instr;
 use span::{Span, Spanned};
 use functions::find_function;
 
-use std::collections::{HashMap, BTreeSet};
+use std::collections::{HashMap, HashSet, BTreeSet};
+use std::mem;
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub enum AnalyseError {
-    IncorrectReturnType(Span),
-    IncorrectTupleTypes(Span),
-    IncorrectBinOpTypes(Span),
-    IncorrectAssignmentType(Span),
-    UndefinedName(Span),
-    ExpectedReturn(Span),
-    ExpectedBoolean(Span),
-    ExpectedVoidExprStmt(Span),
-    InvalidApplication(Span),
+pub struct AnalyseError {
+    pub kind: AnalyseErrorKind,
+    pub span: Span,
+    pub file: Option<String>,
 }
 
-struct Env {
-    names: HashMap<String, instr::Type>,
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum AnalyseErrorKind {
+    // Expected, found
+    IncorrectReturnType(instr::Type, instr::Type),
+    IncorrectTupleTypes(Vec<instr::Type>),
+    IncorrectBinOpTypes(ast::OpKind, instr::Type, instr::Type),
+    IncorrectUnOpType(ast::UnOpKind, instr::Type),
+    // Name, declared type, assigned type
+    IncorrectAssignmentType(String, instr::Type, instr::Type),
+    UndefinedName(String),
+    // A variable used after the block it was assigned in
+    OutOfScope(String),
+    ExpectedReturn,
+    // A branch that doesn't return, beside one that does
+    BranchFallsThrough,
+    // An `if` whose branch returns, but has no `else` to return from
+    ExpectedElse,
+    ExpectedBoolean(instr::Type),
+    ExpectedVoidExprStmt,
+    // An `if` used as a value
+    MissingElse,
+    ExpectedBranchValue,
+    // Then branch, else branch
+    IncorrectBranchTypes(instr::Type, instr::Type),
+    InvalidApplication(String, Vec<instr::Type>),
+}
+
+// Variables are local to the block they're first assigned in, like in GLSL.
+// The innermost scope is last.
+struct Env<'a> {
+    scopes: Vec<HashMap<String, instr::Type>>,
+    // Names whose scope has ended, to explain why they're undefined
+    expired: HashSet<String>,
+    // Instructions computing `if` expressions, which run before the
+    // statement containing them
+    pending: Vec<instr::Instr>,
+    // The script's own names, which temporaries must avoid
+    taken: HashSet<String>,
+    temps: usize,
+    // The type the item must return
+    ret: instr::Type,
     used: BTreeSet<ast::KeyVar>,
+    errors: &'a mut Vec<AnalyseError>,
 }
 
-impl Env {
-    fn new() -> Env {
+impl<'a> Env<'a> {
+    fn new(errors: &'a mut Vec<AnalyseError>, taken: HashSet<String>, ret: instr::Type) -> Env<'a> {
         Env {
-            names: HashMap::new(),
-            used: BTreeSet::new()
+            scopes: vec![HashMap::new()],
+            expired: HashSet::new(),
+            pending: Vec::new(),
+            taken: taken,
+            temps: 0,
+            ret: ret,
+            used: BTreeSet::new(),
+            errors: errors,
         }
     }
 
     fn lookup(&self, name: &str) -> Option<instr::Type> {
-        self.names.get(name).cloned()
+        self.scopes.iter().rev().filter_map(|scope| scope.get(name)).next().cloned()
     }
 
+    // Updates the variable if it's already in scope, or declares it in the
+    // innermost scope
     fn insert<S: Into<String>>(&mut self, name: S, ty: instr::Type) {
-        self.names.insert(name.into(), ty);
+        let name = name.into();
+
+        for scope in self.scopes.iter_mut().rev() {
+            if let Some(t) = scope.get_mut(&name) {
+                *t = ty;
+                return
+            }
+        }
+
+        self.scopes.last_mut().unwrap().insert(name, ty);
+    }
+
+    fn push_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        self.expired.extend(scope.into_iter().map(|(name, _)| name));
+    }
+
+    fn temp(&mut self) -> String {
+        loop {
+            let name = format!("_if{}", self.temps);
+            self.temps += 1;
+
+            if !self.taken.contains(&name) {
+                return name
+            }
+        }
     }
 
     fn use_var(&mut self, var: ast::KeyVar) {
         self.used.insert(var);
     }
+
+    fn error(&mut self, span: Span, kind: AnalyseErrorKind) {
+        self.errors.push(AnalyseError {
+            kind: kind,
+            span: span,
+            file: None,
+        });
+    }
 }
 
+// Errors are collected rather than returned straight away, so a script's
+// independent mistakes are all reported at once. Expressions that fail to
+// analyse get the error type, which every check accepts silently so that one
+// mistake doesn't cascade into more.
 impl ast::AST {
-    pub fn analyse(&self) -> Result<::Shady, AnalyseError> {
+    pub fn analyse(&self) -> Result<::Shady, Vec<AnalyseError>> {
         let mut shady = ::Shady::new();
+        let mut errors = Vec::new();
 
-        for item in &self.0 {
-            shady.push_item(try!(analyse_item(&item)));
+        for item in &self.items {
+            shady.push_item(analyse_item(&mut errors, &item));
+        }
+
+        if errors.is_empty() {
+            Ok(shady)
+        } else {
+            Err(errors)
         }
+    }
+}
+
+fn analyse_item(errors: &mut Vec<AnalyseError>, item: &Spanned<ast::Item>) -> instr::Item {
+    let mut taken = HashSet::new();
+    block_names(&item.data.block.data, &mut taken);
+    let ret = match item.data.item {
+        ast::ItemKind::Image => instr::Type::Vec3,
+    };
+
+    let mut env = Env::new(errors, taken, ret);
+
+    let block = analyse_block(&mut env, &item.data.block, Some(&mut |block, env, expr| {
+        let e = analyse_expr(env, expr);
+        flush(env, &mut block.instrs, &mut block.ret, expr.span);
+        merge_ret(env, &mut block.ret, expr.span, Some(e.ty));
+        block.instrs.push(instr::Instr::Return(e));
+    }));
 
-        Ok(shady)
+    if !always_returns(&block.instrs) {
+        // Point at the closing brace if no single branch is to blame
+        let end = Span { begin: block.span.end - 1, end: block.span.end };
+        let (span, kind) = fall_through(&block).unwrap_or((end, AnalyseErrorKind::ExpectedReturn));
+        env.error(span, kind);
+    }
+
+    instr::Item {
+        ret: ret,
+        kind: item.data.item,
+        instrs: block.instrs,
+        vars: env.used
     }
 }
 
-fn analyse_item(item: &Spanned<ast::Item>) -> Result<instr::Item, AnalyseError> {
-    let mut env = Env::new();
+fn block_names(block: &ast::Block, names: &mut HashSet<String>) {
+    for stmt in &block.stmts {
+        match stmt.data {
+            ast::Stmt::Assignment(ref name, ref expr) => {
+                names.insert(name.clone());
+                expr_names(&expr.data, names);
+            },
+
+            ast::Stmt::Return(ref expr) => expr_names(&expr.data, names),
+            ast::Stmt::Expr(ref stmt) => stmt_names(stmt, names),
+        }
+    }
 
-    let block = try!(analyse_block(&mut env, &item.data.block, Some(&mut |block, env, expr| {
-        let e = try!(analyse_expr(env, expr));
+    if let Some(ref expr) = block.expr {
+        expr_names(&expr.data, names);
+    }
+}
 
-        if let Some(ty) = block.ret {
-            if ty != e.ty {
-                return Err(AnalyseError::IncorrectReturnType(expr.span));
+fn stmt_names(stmt: &ast::ExprStmt, names: &mut HashSet<String>) {
+    match *stmt {
+        ast::ExprStmt::ITE(ref ite) => {
+            expr_names(&ite.0.data, names);
+            block_names(&ite.1.data, names);
+
+            if let Some(ref e) = ite.2 {
+                block_names(&e.data, names);
             }
-        };
+        },
+    }
+}
 
-        println!("{:?}", block);
-        println!("{:?}", e);
+fn expr_names(expr: &ast::Expr, names: &mut HashSet<String>) {
+    match *expr {
+        ast::Expr::KeyVar(_) | ast::Expr::Literal(_) | ast::Expr::Bool(_) => (),
+        ast::Expr::Var(ref name) => { names.insert(name.clone()); },
 
-        block.ret = Some(e.ty);
-        block.instrs.push(instr::Instr::Return(e));
-        Ok(())
-    })));
-
-    match item.data.item {
-        ast::ItemKind::Image => if let Some(instr::Type::Vec3) = block.ret {
-            Ok(instr::Item {
-                ret: instr::Type::Vec3,
-                kind: ast::ItemKind::Image,
-                instrs: block.instrs,
-                vars: env.used
-            })
-        } else {
-            unimplemented!();
+        ast::Expr::App(_, ref exprs) => for expr in exprs {
+            expr_names(&expr.data, names);
+        },
+
+        ast::Expr::Vec2(ref exprs) => {
+            expr_names(&exprs.0.data, names);
+            expr_names(&exprs.1.data, names);
         },
+
+        ast::Expr::Vec3(ref exprs) => {
+            expr_names(&exprs.0.data, names);
+            expr_names(&exprs.1.data, names);
+            expr_names(&exprs.2.data, names);
+        },
+
+        ast::Expr::BinOp(_, ref exprs) => {
+            expr_names(&exprs.0.data, names);
+            expr_names(&exprs.1.data, names);
+        },
+
+        ast::Expr::UnOp(_, ref expr) => expr_names(&expr.data, names),
+        ast::Expr::Stmt(ref stmt) => stmt_names(stmt, names),
     }
 }
 
-fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Option<&mut FnMut(&mut instr::Block, &mut Env, &Spanned<ast::Expr>) -> Result<(), AnalyseError>>) -> Result<instr::Block, AnalyseError> {
+// Whether every path through the instructions reaches a return
+fn always_returns(instrs: &[instr::Instr]) -> bool {
+    instrs.iter().any(|instr| match *instr {
+        instr::Instr::Return(_) => true,
+        instr::Instr::ITE(_, ref t, Some(ref e)) => always_returns(&t.instrs) && always_returns(&e.instrs),
+        _ => false,
+    })
+}
+
+// Finds the branch where a block that doesn't always return falls through: the
+// one beside a branch that does return, looking into the latest `if` first.
+// Nothing is found if the block never returns at all.
+fn fall_through(block: &instr::Block) -> Option<(Span, AnalyseErrorKind)> {
+    for instr in block.instrs.iter().rev() {
+        if let instr::Instr::ITE(_, ref t, ref e) = *instr {
+            let found = match *e {
+                Some(ref e) => match (always_returns(&t.instrs), always_returns(&e.instrs)) {
+                    (true, false) => Some(branch_fall_through(e)),
+                    (false, true) => Some(branch_fall_through(t)),
+                    _ => fall_through(t).or_else(|| fall_through(e)),
+                },
+
+                None if always_returns(&t.instrs) => Some((t.span, AnalyseErrorKind::ExpectedElse)),
+                None => fall_through(t),
+            };
+
+            if found.is_some() {
+                return found
+            }
+        }
+    }
+
+    None
+}
+
+fn branch_fall_through(block: &instr::Block) -> (Span, AnalyseErrorKind) {
+    fall_through(block).unwrap_or((block.span, AnalyseErrorKind::BranchFallsThrough))
+}
+
+// Whether two types agree, treating the error type as matching anything
+fn compatible(a: instr::Type, b: instr::Type) -> bool {
+    a == b || a == instr::Type::Error || b == instr::Type::Error
+}
+
+// Merges a block's return type into the enclosing one, reporting any that
+// isn't the item's
+fn merge_ret(env: &mut Env, ret: &mut Option<instr::Type>, span: Span, ty: Option<instr::Type>) {
+    match (*ret, ty) {
+        (_, Some(found)) if !compatible(env.ret, found) =>
+            env.error(span, AnalyseErrorKind::IncorrectReturnType(env.ret, found)),
+
+        (Some(instr::Type::Error), Some(found)) | (None, Some(found)) => *ret = Some(found),
+        _ => (),
+    }
+}
+
+fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Option<&mut FnMut(&mut instr::Block, &mut Env, &Spanned<ast::Expr>)>) -> instr::Block {
     let mut stmts = Vec::new();
     let mut ret = None;
 
     for stmt in &block.data.stmts {
         match stmt.data {
             ast::Stmt::Assignment(ref name, ref expr) => {
-                let expr = try!(analyse_expr(env, expr));
+                let expr = analyse_expr(env, expr);
+                flush(env, &mut stmts, &mut ret, stmt.span);
 
                 match env.lookup(name) {
-                    Some(ty) => if expr.ty != ty {
-                        return Err(AnalyseError::IncorrectAssignmentType(stmt.span))
-                    } else {
+                    Some(ty) => {
+                        if !compatible(expr.ty, ty) {
+                            env.error(stmt.span, AnalyseErrorKind::IncorrectAssignmentType(name.clone(), ty, expr.ty));
+                        } else if ty == instr::Type::Error {
+                            // The first assignment failed, so take this one's type
+                            env.insert(name.clone(), expr.ty);
+                        }
+
                         stmts.push(instr::Instr::Assignment(name.clone(), expr));
                     },
 
@@ -114,55 +323,29 @@ fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Optio
             },
 
             ast::Stmt::Return(ref expr) => {
-                let expr = try!(analyse_expr(env, expr));
-
-                if let Some(ty) = ret {
-                    if expr.ty != ty {
-                        return Err(AnalyseError::IncorrectReturnType(stmt.span));
-                    }
-                } else {
-                    ret = Some(expr.ty)
-                };
-
+                let expr = analyse_expr(env, expr);
+                flush(env, &mut stmts, &mut ret, stmt.span);
+                merge_ret(env, &mut ret, stmt.span, Some(expr.ty));
                 stmts.push(instr::Instr::Return(expr));
             },
 
             ast::Stmt::Expr(ast::ExprStmt::ITE(ref exprs)) => {
-                let i = try!(analyse_expr(env, &exprs.0));
-                if i.ty != instr::Type::Bool {
-                    return Err(AnalyseError::ExpectedBoolean(exprs.0.span));
+                let i = analyse_expr(env, &exprs.0);
+                if !compatible(i.ty, instr::Type::Bool) {
+                    env.error(exprs.0.span, AnalyseErrorKind::ExpectedBoolean(i.ty));
                 }
 
-                // TODO: Parent environment!!!
-                let t = try!(analyse_block(env, &exprs.1, None));
-                if let Some(ety) = t.ret {
-                    if let Some(ty) = ret {
-                        if ty != ety {
-                            return Err(AnalyseError::IncorrectReturnType(exprs.1.span));
-                        }
-                    } else {
-                        ret = t.ret
-                    }
-                }
+                let t = analyse_branch(env, &exprs.1, None);
+                merge_ret(env, &mut ret, exprs.1.span, t.ret);
 
-                let e = if let Some(ref b) = exprs.2 {
-                    // TODO: Parent environment!!!
-                    let e = try!(analyse_block(env, &b, None));
-                    if let Some(ety) = e.ret {
-                        if let Some(ty) = ret {
-                            if ty != ety {
-                                return Err(AnalyseError::IncorrectReturnType(b.span));
-                            }
-                        } else {
-                            ret = e.ret
-                        }
-                    }
-
-                    Some(e)
-                } else {
-                    None
-                };
+                let e = exprs.2.as_ref().map(|b| {
+                    let e = analyse_branch(env, b, None);
+                    merge_ret(env, &mut ret, b.span, e.ret);
+                    e
+                });
 
+                flush(env, &mut stmts, &mut ret, exprs.0.span);
+                let (t, e) = hoist_shared(env, &mut stmts, t, e);
                 stmts.push(instr::Instr::ITE(i.expr, t, e));
             },
         }
@@ -171,245 +354,550 @@ fn analyse_block(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Optio
     let mut b = instr::Block {
         ret: ret,
         instrs: stmts,
+        span: block.span,
     };
 
     if let Some(ref expr) = block.data.expr {
         if let Some(handler) = expr_handler {
-            try!(handler(&mut b, env, expr))
+            handler(&mut b, env, expr)
         } else {
-            return Err(AnalyseError::ExpectedVoidExprStmt(expr.span))
+            env.error(expr.span, AnalyseErrorKind::ExpectedVoidExprStmt)
         }
     };
 
-    Ok(b)
+    b
+}
+
+// Analyses one branch of an `if` in its own scope
+fn analyse_branch(env: &mut Env, block: &Spanned<ast::Block>, expr_handler: Option<&mut FnMut(&mut instr::Block, &mut Env, &Spanned<ast::Expr>)>) -> instr::Block {
+    // Anything still pending belongs to the statement around the `if`
+    let outer = mem::replace(&mut env.pending, Vec::new());
+
+    env.push_scope();
+    let b = analyse_block(env, block, expr_handler);
+    env.pop_scope();
+
+    env.pending = outer;
+    b
 }
 
-fn analyse_expr(env: &mut Env, expr: &Spanned<ast::Expr>) -> Result<instr::Expr, AnalyseError> {
+// Moves the instructions that `if` expressions need in front of the statement
+// using them, along with any returns they contain
+fn flush(env: &mut Env, instrs: &mut Vec<instr::Instr>, ret: &mut Option<instr::Type>, span: Span) {
+    for instr in mem::replace(&mut env.pending, Vec::new()) {
+        if let instr::Instr::ITE(_, ref t, ref e) = instr {
+            merge_ret(env, ret, span, t.ret);
+
+            if let Some(ref e) = *e {
+                merge_ret(env, ret, span, e.ret);
+            }
+        }
+
+        instrs.push(instr);
+    }
+}
+
+// A variable first assigned in both branches, with the same type, is declared
+// before the `if` so it can be used after it. Anything else stays local to its
+// branch.
+fn hoist_shared(env: &mut Env, instrs: &mut Vec<instr::Instr>, mut t: instr::Block, mut e: Option<instr::Block>) -> (instr::Block, Option<instr::Block>) {
+    if let Some(ref mut e) = e {
+        let theirs = decls(e);
+
+        for (name, ty) in decls(&t) {
+            if theirs.contains(&(name.clone(), ty)) {
+                hoist(&mut t, &name);
+                hoist(e, &name);
+
+                env.insert(name.clone(), ty);
+                env.expired.remove(&name);
+                instrs.push(instr::Instr::Decl(name, ty, None));
+            }
+        }
+    }
+
+    (t, e)
+}
+
+// The variables declared directly in a block, in order
+fn decls(block: &instr::Block) -> Vec<(String, instr::Type)> {
+    block.instrs.iter().filter_map(|instr| match *instr {
+        instr::Instr::Decl(ref name, ty, _) => Some((name.clone(), ty)),
+        _ => None,
+    }).collect()
+}
+
+// Turns a block's declaration of a variable into an assignment, once it has
+// been declared in the enclosing block instead
+fn hoist(block: &mut instr::Block, name: &str) {
+    block.instrs.retain(|instr| match *instr {
+        instr::Instr::Decl(ref n, _, None) => n != name,
+        _ => true,
+    });
+
+    for instr in &mut block.instrs {
+        let assignment = match *instr {
+            instr::Instr::Decl(ref n, ty, Some(ref expr)) if n == name =>
+                instr::Instr::Assignment(n.clone(), instr::Expr { ty: ty, expr: expr.clone() }),
+            _ => continue,
+        };
+
+        *instr = assignment;
+    }
+}
+
+fn analyse_expr(env: &mut Env, expr: &Spanned<ast::Expr>) -> instr::Expr {
     match expr.data {
         ast::Expr::KeyVar(var) => {
             env.use_var(var);
 
-            Ok(instr::Expr {
+            instr::Expr {
                 ty: instr::Type::Float,
                 expr: instr::ExprKind::KeyVar(var)
-            })
+            }
         },
 
-        ast::Expr::Literal(ref lit) => Ok(instr::Expr {
+        ast::Expr::Literal(lit) => instr::Expr {
             ty: instr::Type::Float,
-            expr: instr::ExprKind::Literal(lit.clone())
-        }),
+            expr: instr::ExprKind::Literal(lit)
+        },
 
-        ast::Expr::Bool(b) => Ok(instr::Expr {
+        ast::Expr::Bool(b) => instr::Expr {
             ty: instr::Type::Bool,
             expr: instr::ExprKind::Bool(b)
-        }),
+        },
 
-        ast::Expr::Var(ref name) => env.lookup(name)
-            .ok_or(AnalyseError::UndefinedName(expr.span))
-            .map(|ty| instr::Expr {
+        ast::Expr::Var(ref name) => match env.lookup(name) {
+            Some(ty) => instr::Expr {
                 ty: ty,
                 expr: instr::ExprKind::Var(name.clone())
-            }),
+            },
+
+            None => {
+                let kind = if env.expired.contains(name) {
+                    AnalyseErrorKind::OutOfScope(name.clone())
+                } else {
+                    AnalyseErrorKind::UndefinedName(name.clone())
+                };
+
+                env.error(expr.span, kind);
+
+                // Only report the first use
+                env.insert(name.clone(), instr::Type::Error);
+                instr::Expr::error()
+            }
+        },
 
         ast::Expr::App(ref name, ref exprs) => {
             let mut tys = Vec::new();
             let mut es = Vec::new();
 
             for expr in exprs {
-                let e = try!(analyse_expr(env, &expr));
+                let e = analyse_expr(env, &expr);
                 tys.push(e.ty);
                 es.push(e.expr);
             }
 
-            if let Some(ty) = find_function(name, &tys) {
-                Ok(instr::Expr {
+            if tys.contains(&instr::Type::Error) {
+                instr::Expr::error()
+            } else if let Some(ty) = find_function(name, &tys) {
+                instr::Expr {
                     ty: ty,
                     expr: instr::ExprKind::Application(name.clone(), es)
-                })
+                }
             } else {
-                Err(AnalyseError::InvalidApplication(expr.span))
+                env.error(expr.span, AnalyseErrorKind::InvalidApplication(name.clone(), tys));
+                instr::Expr::error()
             }
         },
 
+        // A tuple's type is known even if its components are wrong
         ast::Expr::Vec2(ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
 
-            if e1.ty != e2.ty {
-                Err(AnalyseError::IncorrectTupleTypes(expr.span))
-            } else {
-                Ok(instr::Expr {
-                    ty: instr::Type::Vec2,
-                    expr: instr::ExprKind::Vec2(Box::new((e1.expr, e2.expr)))
-                })
+            if !compatible(e1.ty, e2.ty) {
+                env.error(expr.span, AnalyseErrorKind::IncorrectTupleTypes(vec![e1.ty, e2.ty]));
+            }
+
+            instr::Expr {
+                ty: instr::Type::Vec2,
+                expr: instr::ExprKind::Vec2(Box::new((e1.expr, e2.expr)))
             }
         },
 
         ast::Expr::Vec3(ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
-            let e3 = try!(analyse_expr(env, &exprs.2));
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
+            let e3 = analyse_expr(env, &exprs.2);
 
-            if e1.ty != e2.ty || e1.ty != e3.ty {
-                Err(AnalyseError::IncorrectTupleTypes(expr.span))
-            } else {
-                Ok(instr::Expr {
-                    ty: instr::Type::Vec3,
-                    expr: instr::ExprKind::Vec3(Box::new((e1.expr, e2.expr, e3.expr)))
-                })
+            if !compatible(e1.ty, e2.ty) || !compatible(e1.ty, e3.ty) || !compatible(e2.ty, e3.ty) {
+                env.error(expr.span, AnalyseErrorKind::IncorrectTupleTypes(vec![e1.ty, e2.ty, e3.ty]));
+            }
+
+            instr::Expr {
+                ty: instr::Type::Vec3,
+                expr: instr::ExprKind::Vec3(Box::new((e1.expr, e2.expr, e3.expr)))
             }
         },
 
-        ast::Expr::BinOp(op @ ast::OpKind::ArithOp(_), ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+        ast::Expr::BinOp(op @ ast::OpKind::ArithOp(kind), ref exprs) => {
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
+
+            match arith_type(kind, e1.ty, e2.ty) {
+                Some(instr::Type::Error) => instr::Expr::error(),
 
-            match (e1.ty, e2.ty) {
-                (instr::Type::Float, ty) | (ty, instr::Type::Float) => Ok(instr::Expr {
+                Some(ty) => instr::Expr {
                     ty: ty,
                     expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                }),
+                },
 
-                (ref t, ref u) if *t == *u => Ok(instr::Expr {
-                    ty: t.clone(),
-                    expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                }),
+                None => {
+                    env.error(expr.span, AnalyseErrorKind::IncorrectBinOpTypes(op, e1.ty, e2.ty));
+                    instr::Expr::error()
+                }
+            }
+        },
+
+        ast::Expr::BinOp(op @ ast::OpKind::CmpOp(kind), ref exprs) => {
+            let e1 = analyse_expr(env, &exprs.0);
+            let e2 = analyse_expr(env, &exprs.1);
+
+            // GLSL's `<` and friends only take scalars, so only floats are
+            // ordered
+            let valid = match kind {
+                ast::CmpOpKind::Eq | ast::CmpOpKind::Ne => compatible(e1.ty, e2.ty),
+                _ => compatible(e1.ty, instr::Type::Float) && compatible(e2.ty, instr::Type::Float),
+            };
 
-                _ => Err(AnalyseError::IncorrectBinOpTypes(expr.span))
+            if !valid {
+                env.error(expr.span, AnalyseErrorKind::IncorrectBinOpTypes(op, e1.ty, e2.ty));
+            }
+
+            instr::Expr {
+                ty: instr::Type::Bool,
+                expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
             }
         },
 
-        ast::Expr::BinOp(op @ ast::OpKind::CmpOp(_), ref exprs) => {
-            let e1 = try!(analyse_expr(env, &exprs.0));
-            let e2 = try!(analyse_expr(env, &exprs.1));
+        ast::Expr::BinOp(op @ ast::OpKind::LogicOp(kind), ref exprs) => {
+            let e1 = analyse_expr(env, &exprs.0);
+
+            // Any `if` expressions in the right operand must only run if it's
+            // evaluated, so they're kept apart from the statement's
+            let outer = mem::replace(&mut env.pending, Vec::new());
+            let e2 = analyse_expr(env, &exprs.1);
+
+            let mut instrs = Vec::new();
+            let mut ret = None;
+            flush(env, &mut instrs, &mut ret, exprs.1.span);
+            env.pending = outer;
 
-            if e1.ty == e2.ty {
-                Ok(instr::Expr {
+            if !compatible(e1.ty, instr::Type::Bool) || !compatible(e2.ty, instr::Type::Bool) {
+                env.error(expr.span, AnalyseErrorKind::IncorrectBinOpTypes(op, e1.ty, e2.ty));
+            }
+
+            if instrs.is_empty() {
+                return instr::Expr {
                     ty: instr::Type::Bool,
                     expr: instr::ExprKind::BinOp(op, Box::new((e1.expr, e2.expr)))
-                })
-            } else {
-                Err(AnalyseError::IncorrectBinOpTypes(expr.span))
+                }
+            }
+
+            // Otherwise the operator is spelled out as an `if` around them
+            let name = env.temp();
+            let cond = match kind {
+                ast::LogicOpKind::And => instr::ExprKind::Var(name.clone()),
+                ast::LogicOpKind::Or => instr::ExprKind::UnOp(ast::UnOpKind::Not, Box::new(instr::ExprKind::Var(name.clone()))),
+            };
+
+            instrs.push(instr::Instr::Assignment(name.clone(), e2));
+
+            let rhs = instr::Block {
+                ret: ret,
+                instrs: instrs,
+                span: exprs.1.span,
+            };
+
+            env.pending.push(instr::Instr::Decl(name.clone(), instr::Type::Bool, Some(e1.expr)));
+            env.pending.push(instr::Instr::ITE(cond, rhs, None));
+
+            instr::Expr {
+                ty: instr::Type::Bool,
+                expr: instr::ExprKind::Var(name)
             }
         },
 
-        ast::Expr::Stmt(_) => unimplemented!()
-    }
-}
+        ast::Expr::UnOp(op @ ast::UnOpKind::Not, ref operand) => {
+            let e = analyse_expr(env, operand);
 
-/*
+            if !compatible(e.ty, instr::Type::Bool) {
+                env.error(expr.span, AnalyseErrorKind::IncorrectUnOpType(op, e.ty));
+            }
 
-    fn expr_type(&self, expr: &ast::Expr) -> Result<instr::Type, AnalyseError> {
-        match expr {
-            &ast::Expr::Literal(_) => Ok(instr::Type::Float),
-            &ast::Expr::Bool(_) => Ok(instr::Type::Bool),
+            instr::Expr {
+                ty: instr::Type::Bool,
+                expr: instr::ExprKind::UnOp(op, Box::new(e.expr))
+            }
+        },
 
-            &ast::Expr::Var(ref name) => self.lookup(name).ok_or(AnalyseError::UndefinedName(name.clone())),
+        ast::Expr::UnOp(op @ ast::UnOpKind::Neg, ref operand) => {
+            let e = analyse_expr(env, operand);
 
-            &ast::Expr::Vec2(ref exprs) => {
-                let ty = try!(self.expr_type(&exprs.0.data));
-                if try!(self.expr_type(&exprs.1.data)) == ty {
-                    Ok(instr::Type::Vec2)
-                } else {
-                    Err(AnalyseError::IncorrectTupleTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
-                }
-            },
+            match e.ty {
+                instr::Type::Error => instr::Expr::error(),
 
-            &ast::Expr::Vec3(ref exprs) => {
-                let ty = try!(self.expr_type(&exprs.0.data));
-                if try!(self.expr_type(&exprs.1.data)) == ty && try!(self.expr_type(&exprs.2.data)) == ty {
-                    Ok(instr::Type::Vec3)
-                } else {
-                    Err(AnalyseError::IncorrectTupleTypes(Span { begin: exprs.0.span.begin, end: exprs.2.span.end }))
+                instr::Type::Float | instr::Type::Vec2 | instr::Type::Vec3 => instr::Expr {
+                    ty: e.ty,
+                    expr: instr::ExprKind::UnOp(op, Box::new(e.expr))
+                },
+
+                ty => {
+                    env.error(expr.span, AnalyseErrorKind::IncorrectUnOpType(op, ty));
+                    instr::Expr::error()
                 }
-            },
+            }
+        },
+
+        // Computed into a temporary ahead of the statement, since GLSL has
+        // no way to run a branch's statements inside an expression
+        ast::Expr::Stmt(ast::ExprStmt::ITE(ref exprs)) => {
+            let i = analyse_expr(env, &exprs.0);
+            if !compatible(i.ty, instr::Type::Bool) {
+                env.error(exprs.0.span, AnalyseErrorKind::ExpectedBoolean(i.ty));
+            }
 
-            &ast::Expr::BinOp(ast::OpKind::ArithOp(_), ref exprs) => {
-                match (try!(self.expr_type(&exprs.0.data)), try!(self.expr_type(&exprs.1.data))) {
-                    (instr::Type::Float, ty) | (ty, instr::Type::Float) => Ok(ty),
-                    (ref a, ref b) if *a == *b => Ok(*a),
-                    _ => Err(AnalyseError::IncorrectBinOpTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
+            let els = match exprs.2 {
+                Some(ref els) => els,
+                None => {
+                    env.error(expr.span, AnalyseErrorKind::MissingElse);
+                    return instr::Expr::error()
                 }
-            },
+            };
+
+            let name = env.temp();
+            let mut tys = Vec::new();
+
+            let (t, e) = {
+                let mut assign = |block: &mut instr::Block, env: &mut Env, expr: &Spanned<ast::Expr>| {
+                    let e = analyse_expr(env, expr);
+                    flush(env, &mut block.instrs, &mut block.ret, expr.span);
 
-            &ast::Expr::BinOp(ast::OpKind::CmpOp(_), ref exprs) => {
-                match (try!(self.expr_type(&exprs.0.data)), try!(self.expr_type(&exprs.1.data))) {
-                    (ref a, ref b) if *a == *b => Ok(*a),
-                    _ => Err(AnalyseError::IncorrectBinOpTypes(Span { begin: exprs.0.span.begin, end: exprs.1.span.end }))
+                    tys.push((expr.span, e.ty));
+                    block.instrs.push(instr::Instr::Assignment(name.clone(), e));
+                };
+
+                (analyse_branch(env, &exprs.1, Some(&mut assign)), analyse_branch(env, els, Some(&mut assign)))
+            };
+
+            // A branch that returns doesn't need a value
+            for block in &[&exprs.1, els] {
+                let returns = match block.data.stmts.last() {
+                    Some(&Spanned { data: ast::Stmt::Return(_), .. }) => true,
+                    _ => false,
+                };
+
+                if block.data.expr.is_none() && !returns {
+                    env.error(block.span, AnalyseErrorKind::ExpectedBranchValue);
                 }
-            },
+            }
 
-            &ast::Expr::Stmt(ast::ExprStmt::ITE(ref ite)) => {
+            let ty = match (tys.get(0).cloned(), tys.get(1).cloned()) {
+                (Some((_, t)), Some((span, u))) if !compatible(t, u) => {
+                    env.error(span, AnalyseErrorKind::IncorrectBranchTypes(t, u));
+                    instr::Type::Error
+                },
+
+                (Some((_, instr::Type::Error)), Some((_, ty))) | (Some((_, ty)), _) | (None, Some((_, ty))) => ty,
+                (None, None) => instr::Type::Error,
+            };
 
+            if ty == instr::Type::Error {
+                return instr::Expr::error()
             }
-        }
-    }
 
-impl ast::AST {
-    pub fn analyse(&self) -> Result<::Shady, AnalyseError> {
-        let mut shady = ::Shady::new();
+            let mut instrs = Vec::new();
+            let (t, e) = hoist_shared(env, &mut instrs, t, Some(e));
 
-        for item in &self.0 {
-            shady.push_item(try!(item.data.analyse()));
-        }
+            env.pending.extend(instrs);
+            env.pending.push(instr::Instr::Decl(name.clone(), ty, None));
+            env.pending.push(instr::Instr::ITE(i.expr, t, e));
 
-        Ok(shady)
+            instr::Expr {
+                ty: ty,
+                expr: instr::ExprKind::Var(name)
+            }
+        },
     }
 }
 
-impl ast::Item {
-    pub fn analyse(&self) -> Result<instr::Item, AnalyseError> {
-        let mut item = instr::Item::new(self.item);
-        let mut env = Env::new(self.item);
-
-        for stmt in &self.block.data.stmts {
-            match &stmt.data {
-                &ast::Stmt::Assignment(ref name, ref expr) => {
-                    let ty = try!(env.expr_type(&expr.data));
-                    env.insert(name.clone(), ty);
-                    item.push_instr(instr::ass(ty, name.clone(), expr.data.clone()));
-                },
+// Arithmetic works on floats and vectors of the same size, and the basic
+// operators also apply a float to every component of a vector. Like GLSL's
+// `mod`, `%` only takes the float on the right, and `^` needs matching types.
+fn arith_type(op: ast::ArithOpKind, t: instr::Type, u: instr::Type) -> Option<instr::Type> {
+    use instr::Type::{Float, Vec2, Vec3, Error};
+
+    match (op, t, u) {
+        (_, Error, _) | (_, _, Error) => Some(Error),
+        (_, Float, Float) | (_, Vec2, Vec2) | (_, Vec3, Vec3) => Some(t),
+        (ast::ArithOpKind::Pow, _, _) => None,
+        (_, Vec2, Float) | (_, Vec3, Float) => Some(t),
+        (ast::ArithOpKind::Mod, _, _) => None,
+        (_, Float, Vec2) | (_, Float, Vec3) => Some(u),
+        _ => None,
+    }
+}
 
-                &ast::Stmt::Return(ref expr) => if try!(env.expr_type(&expr.data)) == item.ret {
-                    item.push_instr(instr::ret(expr.data.clone()));
-                } else {
-                    return Err(AnalyseError::IncorrectReturnType(stmt.span));
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use ::{ast, instr};
+    use super::{AnalyseError, AnalyseErrorKind};
+
+    fn errors(src: &str) -> Vec<AnalyseError> {
+        ::parse_input(src).unwrap().analyse().unwrap_err()
+    }
+
+    #[test]
+    fn reports_every_error() {
+        let errs = errors("image { a = foo(x); b = (x, y) + (x, y, t); c = (1, a) * sin(x, y); if b { return c; }; (z, a, 0) }");
+
+        assert_eq!(errs.len(), 4, "{:?}", errs);
+        match (&errs[0].kind, &errs[1].kind, &errs[2].kind, &errs[3].kind) {
+            (&AnalyseErrorKind::InvalidApplication(..),
+             &AnalyseErrorKind::IncorrectBinOpTypes(..),
+             &AnalyseErrorKind::InvalidApplication(..),
+             &AnalyseErrorKind::UndefinedName(..)) => (),
+            _ => panic!("unexpected errors {:?}", errs),
         }
+    }
 
-        if let Some(ref expr) = self.block.data.expr {
-            if try!(env.expr_type(&expr.data)) == item.ret {
-                item.push_instr(instr::ret(expr.data.clone()));
-            } else {
-                return Err(AnalyseError::IncorrectReturnType(expr.span));
-            }
+    #[test]
+    fn operator_types() {
+        let errs = errors("image { a = (x, y, 0) % 2; b = -a ^ a + 2 * a; c = 2 % a; d = (x, y) ^ 2; e = -true; f = true + 1; g = x && true; h = !x; i = x <= y || x != 1; a }");
+        let kinds: Vec<AnalyseErrorKind> = errs.into_iter().map(|err| err.kind).collect();
+
+        let mod_op = ast::OpKind::ArithOp(ast::ArithOpKind::Mod);
+        let pow_op = ast::OpKind::ArithOp(ast::ArithOpKind::Pow);
+        let add_op = ast::OpKind::ArithOp(ast::ArithOpKind::Add);
+        let and_op = ast::OpKind::LogicOp(ast::LogicOpKind::And);
+
+        assert_eq!(kinds, vec![
+            AnalyseErrorKind::IncorrectBinOpTypes(mod_op, instr::Type::Float, instr::Type::Vec3),
+            AnalyseErrorKind::IncorrectBinOpTypes(pow_op, instr::Type::Vec2, instr::Type::Float),
+            AnalyseErrorKind::IncorrectUnOpType(ast::UnOpKind::Neg, instr::Type::Bool),
+            AnalyseErrorKind::IncorrectBinOpTypes(add_op, instr::Type::Bool, instr::Type::Float),
+            AnalyseErrorKind::IncorrectBinOpTypes(and_op, instr::Type::Float, instr::Type::Bool),
+            AnalyseErrorKind::IncorrectUnOpType(ast::UnOpKind::Not, instr::Type::Float),
+        ]);
+
+        // Only floats are ordered, but anything can be tested for equality
+        let errs = errors("image { a = (x, y) >= (mx, my); b = true < false; c = x > (y, y); d = (x, y) != (y, x) && a == b; (x, y, t) }");
+        let kinds: Vec<AnalyseErrorKind> = errs.into_iter().map(|err| err.kind).collect();
+
+        let ge_op = ast::OpKind::CmpOp(ast::CmpOpKind::Ge);
+        let lt_op = ast::OpKind::CmpOp(ast::CmpOpKind::Lt);
+        let gt_op = ast::OpKind::CmpOp(ast::CmpOpKind::Gt);
+
+        assert_eq!(kinds, vec![
+            AnalyseErrorKind::IncorrectBinOpTypes(ge_op, instr::Type::Vec2, instr::Type::Vec2),
+            AnalyseErrorKind::IncorrectBinOpTypes(lt_op, instr::Type::Bool, instr::Type::Bool),
+            AnalyseErrorKind::IncorrectBinOpTypes(gt_op, instr::Type::Float, instr::Type::Vec2),
+        ]);
+    }
+
+    #[test]
+    fn no_cascades() {
+        // Both uses of `a` depend on the undefined `b`, so only it is reported
+        assert_eq!(errors("image { a = b * 2; c = (a, a, a) + sin(a); c }").len(), 1);
+    }
+
+    #[test]
+    fn branch_scopes() {
+        let errs = errors("image { if x < y { a = 1; b = x; } else { a = 2; b = (x, y); c = 3; }; (a, b, c) }");
+        let kinds: Vec<AnalyseErrorKind> = errs.into_iter().map(|err| err.kind).collect();
+
+        assert_eq!(kinds, vec![
+            AnalyseErrorKind::OutOfScope("b".to_owned()),
+            AnalyseErrorKind::OutOfScope("c".to_owned()),
+        ]);
+
+        // Only `c` is assigned in both branches, so it alone is hoisted
+        let shady = ::compile("image { if x < y { c = (x, y, 0); d = c; } else { c = (y, x, 0); }; c }").unwrap();
+        assert_eq!(format!("{}", shady), "image(x, y) -> vec3 {\n    decl vec3 c\n    if (x < y) {\n        c = vec3(x, y, 0) : vec3\n        decl vec3 d = c\n    } else {\n        c = vec3(y, x, 0) : vec3\n    }\n    return c : vec3\n}\n");
+    }
+
+    #[test]
+    fn missing_returns() {
+        let kinds = |src| -> Vec<(AnalyseErrorKind, String)> {
+            errors(src).into_iter().map(|err| (err.kind, src[err.span.begin..err.span.end].to_owned())).collect()
         };
 
-        if let Some(&instr::Instr::Return(_)) = item.instrs.0.last() {
-            Ok(item)
-        } else {
-            return Err(AnalyseError::ExpectedReturn(self.block.span))
-        }
+        assert_eq!(kinds("image { a = x; }"), vec![(AnalyseErrorKind::ExpectedReturn, "}".to_owned())]);
+        assert_eq!(kinds("image { if x < y { return (x, y, 0); }; }"), vec![(AnalyseErrorKind::ExpectedElse, "{ return (x, y, 0); }".to_owned())]);
+        assert_eq!(
+            kinds("image { if x < y { if y < 0.5 { a = 1; } else { return (x, y, 0); }; } else { return (y, x, 0); }; }"),
+            vec![(AnalyseErrorKind::BranchFallsThrough, "{ a = 1; }".to_owned())]
+        );
 
+        assert!(::compile("image { if x < y { return (x, y, 0); } else { if y < 0.5 { return (1, 1, 1); }; }; (0, 0, 0) }").is_ok());
+        assert!(::compile("image { if x < y { return (x, y, 0); } else { return (y, x, 0); }; }").is_ok());
     }
-}
 
-impl instr::Item {
-    fn new(kind: ast::ItemKind) -> instr::Item {
-        match kind {
-            ast::ItemKind::Image => instr::Item {
-                ret: instr::Type::Vec3,
-                kind: ast::ItemKind::Image,
-                instrs: instr::Block(Vec::new())
-            }
+    #[test]
+    fn return_types() {
+        // Every return is checked against the image's type, not the first one
+        let kinds: Vec<AnalyseErrorKind> = errors("image { if x < y { return 1; }; if x { return (x, y, 0); }; x }").into_iter().map(|err| err.kind).collect();
+
+        assert_eq!(kinds, vec![
+            AnalyseErrorKind::IncorrectReturnType(instr::Type::Vec3, instr::Type::Float),
+            AnalyseErrorKind::ExpectedBoolean(instr::Type::Float),
+            AnalyseErrorKind::IncorrectReturnType(instr::Type::Vec3, instr::Type::Float),
+        ]);
+    }
+
+    #[test]
+    fn short_circuits() {
+        let src = "image { if x > 0.5 && (if y < 0.5 { return (1, 0, 0); } else { true }) { return (0, 1, 0); }; if x < 0.25 || (if y > 0.5 { return (0, 0, 1); } else { false }) { return (1, 1, 1); }; (0, 0, 0) }";
+        let sdy = ::compile(src).unwrap();
+
+        let cases = [
+            (0.75, 0.25, ::Value::Vec3([1.0, 0.0, 0.0])),
+            (0.75, 0.75, ::Value::Vec3([0.0, 1.0, 0.0])),
+            (0.1, 0.75, ::Value::Vec3([1.0, 1.0, 1.0])),
+            (0.4, 0.75, ::Value::Vec3([0.0, 0.0, 1.0])),
+            (0.4, 0.25, ::Value::Vec3([0.0, 0.0, 0.0])),
+        ];
+
+        for &(x, y, ref expected) in &cases {
+            let inputs = ::Inputs { x: x, y: y, ..::Inputs::default() };
+            assert_eq!(&sdy.get(0).eval(&inputs), expected, "at ({}, {})", x, y);
+        }
+
+        // The left operands always decide these, so neither `return` may run
+        let src = "image { a = x > 2 && (if y < 2 { return (1, 0, 0); } else { true }); b = x < 2 || (if y < 2 { return (0, 1, 0); } else { false }); if a || !b { return (1, 1, 1); }; (0, 0, 1) }";
+        let sdy = ::compile(src).unwrap();
+
+        for &(x, y) in &[(0.25, 0.75), (0.75, 0.25)] {
+            let inputs = ::Inputs { x: x, y: y, ..::Inputs::default() };
+            assert_eq!(sdy.get(0).eval(&inputs), ::Value::Vec3([0.0, 0.0, 1.0]), "at ({}, {})", x, y);
         }
     }
 
-    fn push_instr(&mut self, instr: instr::Instr) {
-        self.instrs.0.push(instr)
+    #[test]
+    fn if_expressions() {
+        let errs = errors("image { a = if x < y { 1 }; b = if x < y { (x, y) } else { 1 }; c = if x { 1 } else { d = 2; }; (a, b, c) }");
+        let kinds: Vec<AnalyseErrorKind> = errs.into_iter().map(|err| err.kind).collect();
+
+        assert_eq!(kinds, vec![
+            AnalyseErrorKind::MissingElse,
+            AnalyseErrorKind::IncorrectBranchTypes(instr::Type::Vec2, instr::Type::Float),
+            AnalyseErrorKind::ExpectedBoolean(instr::Type::Float),
+            AnalyseErrorKind::ExpectedBranchValue,
+        ]);
+
+        // The value goes through a temporary that can't clash with the script's names
+        let shady = ::compile("image { _if0 = 1; c = if x < 0.5 { (1, 0, 0) } else { (0, 0, _if0) }; c }").unwrap();
+        assert_eq!(format!("{}", shady), "image(x) -> vec3 {\n    decl float _if0 = 1\n    decl vec3 _if1\n    if (x < 0.5) {\n        _if1 = vec3(1, 0, 0) : vec3\n    } else {\n        _if1 = vec3(0, 0, _if0) : vec3\n    }\n    decl vec3 c = _if1\n    return c : vec3\n}\n");
     }
 }
-
-*/
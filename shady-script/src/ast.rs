@@ -1,9 +1,13 @@
 use span::Spanned;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub struct AST(pub Vec<Spanned<Item>>);
+#[derive(Debug, PartialEq, Clone)]
+pub struct AST {
+    pub items: Vec<Spanned<Item>>,
+    // The names of lints to silence for the whole script
+    pub allow: Vec<Spanned<String>>,
+}
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Item {
     pub block: Spanned<Block>,
     pub item: ItemKind
@@ -14,29 +18,30 @@ pub enum ItemKind {
     Image,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Block {
     pub stmts: Vec<Spanned<Stmt>>, 
     pub expr: Option<Spanned<Expr>>
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Stmt {
     Assignment(String, Spanned<Expr>),
     Return(Spanned<Expr>),
     Expr(ExprStmt),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     KeyVar(KeyVar),
-    Literal(String),
+    Literal(f64),
     Bool(bool),
     Var(String),
     App(String, Vec<Spanned<Expr>>),
     Vec2(Box<(Spanned<Expr>, Spanned<Expr>)>),
     Vec3(Box<(Spanned<Expr>, Spanned<Expr>, Spanned<Expr>)>),
     BinOp(OpKind, Box<(Spanned<Expr>, Spanned<Expr>)>),
+    UnOp(UnOpKind, Box<Spanned<Expr>>),
     Stmt(ExprStmt),
 }
 
@@ -49,7 +54,7 @@ pub enum KeyVar {
     MouseY
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum ExprStmt {
     ITE(Box<(Spanned<Expr>, Spanned<Block>, Option<Spanned<Block>>)>),
 }
@@ -58,6 +63,7 @@ pub enum ExprStmt {
 pub enum OpKind {
     ArithOp(ArithOpKind),
     CmpOp(CmpOpKind),
+    LogicOp(LogicOpKind),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -66,6 +72,9 @@ pub enum ArithOpKind {
     Sub,
     Mul,
     Div,
+    // Floored like GLSL's `mod`, so the result has the sign of the divisor
+    Mod,
+    Pow,
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -73,6 +82,22 @@ pub enum CmpOpKind {
     Lt,
     Gt,
     Eq,
+    Le,
+    Ge,
+    Ne,
+}
+
+// Only evaluate the right operand when the left doesn't decide the result
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LogicOpKind {
+    And,
+    Or,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum UnOpKind {
+    Neg,
+    Not,
 }
 
 pub fn image(block: Spanned<Block>) -> Item {
@@ -97,8 +122,8 @@ pub fn ret(expr: Spanned<Expr>) -> Stmt {
     Stmt::Return(expr)
 }
 
-pub fn lit<S: Into<String>>(s: S) -> Expr {
-    Expr::Literal(s.into())
+pub fn lit(value: f64) -> Expr {
+    Expr::Literal(value)
 }
 
 pub fn var<S: Into<String>>(s: S) -> Expr {
@@ -133,6 +158,22 @@ pub fn div(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::ArithOp(ArithOpKind::Div), Box::new((a, b)))
 }
 
+pub fn rem(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::ArithOp(ArithOpKind::Mod), Box::new((a, b)))
+}
+
+pub fn pow(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::ArithOp(ArithOpKind::Pow), Box::new((a, b)))
+}
+
+pub fn neg(a: Spanned<Expr>) -> Expr {
+    Expr::UnOp(UnOpKind::Neg, Box::new(a))
+}
+
+pub fn not(a: Spanned<Expr>) -> Expr {
+    Expr::UnOp(UnOpKind::Not, Box::new(a))
+}
+
 pub fn lt(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::CmpOp(CmpOpKind::Lt), Box::new((a, b)))
 }
@@ -145,6 +186,26 @@ pub fn eq(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
     Expr::BinOp(OpKind::CmpOp(CmpOpKind::Eq), Box::new((a, b)))
 }
 
+pub fn le(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Le), Box::new((a, b)))
+}
+
+pub fn ge(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Ge), Box::new((a, b)))
+}
+
+pub fn ne(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::CmpOp(CmpOpKind::Ne), Box::new((a, b)))
+}
+
+pub fn and(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::LogicOp(LogicOpKind::And), Box::new((a, b)))
+}
+
+pub fn or(a: Spanned<Expr>, b: Spanned<Expr>) -> Expr {
+    Expr::BinOp(OpKind::LogicOp(LogicOpKind::Or), Box::new((a, b)))
+}
+
 pub fn ite(i: Spanned<Expr>, t: Spanned<Block>, e: Option<Spanned<Block>>) -> ExprStmt {
     ExprStmt::ITE(Box::new((i, t, e)))
 }
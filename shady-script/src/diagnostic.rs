@@ -0,0 +1,389 @@
+use std::cmp;
+use std::f32;
+use std::fmt::{self, Write};
+
+use ::{ast, instr, pretty};
+use analyse::{AnalyseError, AnalyseErrorKind};
+use error::{ParseError, ParseErrorKind};
+use lint::{Warning, WarningKind, LINTS};
+use functions::FUNCTIONS;
+use span::Span;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+// A message about a span of a script, which can be rendered alongside the
+// source it refers to.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub span: Span,
+    pub message: String,
+    pub hint: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic {
+            level: Level::Error,
+            span: span,
+            message: message.into(),
+            hint: None,
+        }
+    }
+
+    pub fn warning<S: Into<String>>(span: Span, message: S) -> Diagnostic {
+        Diagnostic {
+            level: Level::Warning,
+            ..Diagnostic::error(span, message)
+        }
+    }
+
+    pub fn with_hint<S: Into<String>>(mut self, hint: S) -> Diagnostic {
+        self.hint = Some(hint.into());
+        self
+    }
+
+    // Renders the diagnostic with the lines of `source` it covers, underlining
+    // the span. `name` is used to refer to the source, usually its path.
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let begin = cmp::min(self.span.begin, source.len());
+        let end = cmp::min(cmp::max(self.span.end, begin), source.len());
+
+        let lines: Vec<&str> = source.split('\n').map(|line| line.trim_right_matches('\r')).collect();
+        let (line, col) = line_col(source, begin);
+        let (mut end_line, mut end_col) = line_col(source, end);
+
+        // Don't show an empty line for spans ending with a newline
+        if end_line > line && end_col == 1 {
+            end_line -= 1;
+            end_col = lines[end_line - 1].chars().count() + 1;
+        }
+
+        let width = end_line.to_string().len();
+        let mut out = String::new();
+
+        let _ = writeln!(out, "{}: {}", self.level, self.message);
+        let _ = writeln!(out, "{:w$}--> {}:{}:{}", "", name, line, col, w = width);
+        let _ = writeln!(out, "{:w$} |", "", w = width);
+
+        for n in line..end_line + 1 {
+            let text = lines[n - 1];
+            // Continuation lines are underlined from their first non-blank character
+            let from = if n == line { col - 1 } else { text.chars().take_while(|c| c.is_whitespace()).count() };
+            let to = if n == end_line { end_col - 1 } else { text.chars().count() };
+
+            // Copy tabs so the carets line up however the terminal shows them
+            let mut carets: String = text.chars().take(from).map(|c| if c == '\t' { '\t' } else { ' ' }).collect();
+            for _ in 0..cmp::max(to.saturating_sub(from), if n == line { 1 } else { 0 }) {
+                carets.push('^');
+            }
+
+            let _ = writeln!(out, "{} | {}", pad(n, width), text);
+            let _ = writeln!(out, "{}", format!("{:w$} | {}", "", carets, w = width).trim_right());
+        }
+
+        if let Some(ref hint) = self.hint {
+            let _ = writeln!(out, "{:w$} = hint: {}", "", hint, w = width);
+        }
+
+        out
+    }
+}
+
+fn pad(n: usize, width: usize) -> String {
+    format!("{:>w$}", n, w = width)
+}
+
+// Converts a byte offset into a 1-based line and column, counting columns in
+// characters.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..cmp::min(offset, source.len())];
+    let line = before.matches('\n').count() + 1;
+    let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+
+    (line, col)
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self.kind {
+            ParseErrorKind::InvalidToken =>
+                Diagnostic::error(self.span, "invalid token"),
+
+            ParseErrorKind::UnrecognizedToken(ref text, ref expected) =>
+                with_expected(Diagnostic::error(self.span, format!("unexpected `{}`", text)), expected),
+
+            ParseErrorKind::UnexpectedEnd(ref expected) =>
+                with_expected(Diagnostic::error(self.span, "unexpected end of script"), expected),
+
+            ParseErrorKind::ExtraToken(ref text) =>
+                Diagnostic::error(self.span, format!("unexpected `{}` after the last image", text))
+                    .with_hint("scripts may only contain images"),
+
+            ParseErrorKind::UnexpectedCharacter(c) =>
+                Diagnostic::error(self.span, format!("unexpected character `{}`", c)),
+
+            ParseErrorKind::UnterminatedComment =>
+                Diagnostic::error(self.span, "unterminated block comment")
+                    .with_hint("block comments are closed with `*/`"),
+
+            ParseErrorKind::MissingExponent =>
+                Diagnostic::error(self.span, "missing exponent in number")
+                    .with_hint("exponents are written like `1e3` or `2.5e-2`"),
+
+            ParseErrorKind::NumberOutOfRange =>
+                Diagnostic::error(self.span, "number is too large")
+                    .with_hint(format!("the largest number allowed is about {:e}", f32::MAX)),
+        }
+    }
+}
+
+fn with_expected(diagnostic: Diagnostic, expected: &[String]) -> Diagnostic {
+    if expected.is_empty() {
+        return diagnostic
+    }
+
+    // Terminals are reported quoted, e.g. "\";\"", apart from names and numbers
+    let names: Vec<String> = expected.iter().map(|name| if name == "Number" {
+        "a number".to_owned()
+    } else if name == "Name" {
+        "a name".to_owned()
+    } else if name.len() > 1 && name.starts_with('"') && name.ends_with('"') {
+        format!("`{}`", &name[1..name.len() - 1])
+    } else {
+        name.clone()
+    }).collect();
+
+    match names.len() {
+        1 => diagnostic.with_hint(format!("expected {}", names[0])),
+        _ => diagnostic.with_hint(format!("expected one of {}", names.join(", "))),
+    }
+}
+
+impl AnalyseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.span;
+
+        match self.kind {
+            AnalyseErrorKind::IncorrectReturnType(expected, found) =>
+                Diagnostic::error(span, format!("expected a return value of type {}, found {}", expected, found))
+                    .with_hint(format!("an image returns its colour as a {}", expected)),
+
+            AnalyseErrorKind::IncorrectTupleTypes(ref tys) =>
+                Diagnostic::error(span, format!("mismatched tuple components ({})", types(tys)))
+                    .with_hint("every component of a tuple must have the same type"),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::ArithOp(ast::ArithOpKind::Mod), t, u) =>
+                Diagnostic::error(span, format!("cannot apply `{}` to {} and {}", pretty::op(op), t, u))
+                    .with_hint(format!("`{}` takes two operands of the same type, or a vector and a float", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::ArithOp(ast::ArithOpKind::Pow), t, u) =>
+                Diagnostic::error(span, format!("cannot apply `{}` to {} and {}", pretty::op(op), t, u))
+                    .with_hint(format!("`{}` takes two floats or two vectors of the same size", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::ArithOp(_), t, u) =>
+                Diagnostic::error(span, format!("cannot apply `{}` to {} and {}", pretty::op(op), t, u))
+                    .with_hint(format!("`{}` takes two operands of the same type, or a float and a vector", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::CmpOp(ast::CmpOpKind::Eq), t, u) |
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::CmpOp(ast::CmpOpKind::Ne), t, u) =>
+                Diagnostic::error(span, format!("cannot compare {} with {}", t, u))
+                    .with_hint(format!("`{}` takes two operands of the same type", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::CmpOp(_), t, u) =>
+                Diagnostic::error(span, format!("cannot compare {} with {}", t, u))
+                    .with_hint(format!("`{}` only compares floats; compare vectors one component at a time", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectBinOpTypes(op @ ast::OpKind::LogicOp(_), t, u) =>
+                Diagnostic::error(span, format!("cannot apply `{}` to {} and {}", pretty::op(op), t, u))
+                    .with_hint(format!("`{}` takes two bools", pretty::op(op))),
+
+            AnalyseErrorKind::IncorrectUnOpType(ast::UnOpKind::Neg, ty) =>
+                Diagnostic::error(span, format!("cannot negate a {}", ty))
+                    .with_hint("only floats and vectors can be negated"),
+
+            AnalyseErrorKind::IncorrectUnOpType(op @ ast::UnOpKind::Not, ty) =>
+                Diagnostic::error(span, format!("cannot apply `{}` to a {}", pretty::unop(op), ty))
+                    .with_hint(format!("`{}` takes a bool", pretty::unop(op))),
+
+            AnalyseErrorKind::IncorrectAssignmentType(ref name, declared, found) =>
+                Diagnostic::error(span, format!("cannot assign a {} to `{}`", found, name))
+                    .with_hint(format!("`{}` was first assigned a {}, and variables cannot change type", name, declared)),
+
+            AnalyseErrorKind::UndefinedName(ref name) =>
+                Diagnostic::error(span, format!("undefined name `{}`", name))
+                    .with_hint("variables must be assigned before they are used; the inputs are x, y, t, mx and my"),
+
+            AnalyseErrorKind::OutOfScope(ref name) =>
+                Diagnostic::error(span, format!("`{}` is not defined here", name))
+                    .with_hint(format!("`{}` was only assigned inside an `if` block; assign it before the `if`, or in both branches, to use it afterwards", name)),
+
+            AnalyseErrorKind::ExpectedReturn =>
+                Diagnostic::error(span, "image does not return a colour")
+                    .with_hint("end the image with a vec3 expression, or `return` one"),
+
+            AnalyseErrorKind::BranchFallsThrough =>
+                Diagnostic::error(span, "branch does not return a colour")
+                    .with_hint("the other branch returns, but this one carries on past the end of the image; `return` here too, or after the `if`"),
+
+            AnalyseErrorKind::ExpectedElse =>
+                Diagnostic::error(span, "image does not return a colour when this `if` is skipped")
+                    .with_hint("add an `else` block that returns, or `return` after the `if`"),
+
+            AnalyseErrorKind::ExpectedBoolean(ty) =>
+                Diagnostic::error(span, format!("expected a bool condition, found {}", ty))
+                    .with_hint("comparisons such as `x < 0.5` produce a bool"),
+
+            AnalyseErrorKind::ExpectedVoidExprStmt =>
+                Diagnostic::error(span, "unexpected value at the end of a block")
+                    .with_hint("only the outermost block of an image can end with a value; use `return` instead"),
+
+            AnalyseErrorKind::MissingElse =>
+                Diagnostic::error(span, "`if` used as a value has no `else` branch")
+                    .with_hint("add an `else` block giving the value when the condition is false"),
+
+            AnalyseErrorKind::ExpectedBranchValue =>
+                Diagnostic::error(span, "branch of an `if` expression has no value")
+                    .with_hint("end the block with an expression, without a `;` after it"),
+
+            AnalyseErrorKind::IncorrectBranchTypes(t, e) =>
+                Diagnostic::error(span, "`if` and `else` have different types")
+                    .with_hint(format!("the `if` branch is a {}, but the `else` branch is a {}", t, e)),
+
+            AnalyseErrorKind::InvalidApplication(ref name, ref args) => {
+                let overloads: Vec<String> = FUNCTIONS.iter()
+                    .filter(|f| f.name == name)
+                    .map(|f| format!("{}({})", f.name, types(f.args)))
+                    .collect();
+
+                if overloads.is_empty() {
+                    let mut names: Vec<&str> = FUNCTIONS.iter().map(|f| f.name).collect();
+                    names.dedup();
+
+                    Diagnostic::error(span, format!("unknown function `{}`", name))
+                        .with_hint(format!("the available functions are {}", names.join(", ")))
+                } else {
+                    Diagnostic::error(span, format!("`{}` cannot be applied to ({})", name, types(args)))
+                        .with_hint(format!("expected {}", overloads.join(" or ")))
+                }
+            },
+        }
+    }
+}
+
+impl Warning {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = self.span;
+
+        let diagnostic = match self.kind {
+            WarningKind::UnusedVariable(ref name) =>
+                Diagnostic::warning(span, format!("`{}` is assigned but never used", name)),
+
+            WarningKind::UnreachableCode =>
+                Diagnostic::warning(span, "unreachable code")
+                    .with_hint("the `return` before this always returns"),
+
+            WarningKind::ConstantCondition(b) =>
+                Diagnostic::warning(span, format!("condition is always {}", b))
+                    .with_hint(if b { "the else block never runs" } else { "the if block never runs" }),
+
+            WarningKind::FlatImage =>
+                Diagnostic::warning(span, "image does not depend on x or y")
+                    .with_hint("every pixel will be the same colour"),
+
+            WarningKind::Shadowing(ref name) =>
+                Diagnostic::warning(span, format!("`{}` is a new variable, named after one that is out of scope", name))
+                    .with_hint(format!("the earlier `{}` was only assigned inside an `if` block, so this doesn't change it", name)),
+
+            WarningKind::UnknownLint(ref name) =>
+                Diagnostic::warning(span, format!("unknown lint `{}`", name))
+                    .with_hint(format!("the lints are {}", LINTS.join(", "))),
+        };
+
+        match self.kind.lint() {
+            Some(lint) if diagnostic.hint.is_none() => diagnostic.with_hint(format!("add `allow({});` to the top of the script to silence this", lint)),
+            _ => diagnostic,
+        }
+    }
+}
+
+fn types(tys: &[instr::Type]) -> String {
+    tys.iter().map(|ty| ty.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::line_col;
+
+    fn render(src: &str) -> String {
+        match ::parse_input(src) {
+            Ok(ast) => ast.analyse().unwrap_err()[0].diagnostic().render("test.shy", src),
+            Err(errs) => errs[0].diagnostic().render("test.shy", src),
+        }
+    }
+
+    #[test]
+    fn positions() {
+        let src = "image {\n\tc = 1;\n}";
+        assert_eq!(line_col(src, 0), (1, 1));
+        assert_eq!(line_col(src, 8), (2, 1));
+        assert_eq!(line_col(src, 9), (2, 2));
+        assert_eq!(line_col(src, src.len()), (3, 2));
+    }
+
+    #[test]
+    fn analyse_errors() {
+        assert_eq!(render("image {\n    c = (x, y) + (x, y, t);\n    c\n}"), "\
+error: cannot apply `+` to vec2 and vec3
+ --> test.shy:2:9
+  |
+2 |     c = (x, y) + (x, y, t);
+  |         ^^^^^^^^^^^^^^^^^^
+  = hint: `+` takes two operands of the same type, or a float and a vector
+");
+
+        assert_eq!(render("image { (sin(x, y), 0, 0) }"), "\
+error: `sin` cannot be applied to (float, float)
+ --> test.shy:1:10
+  |
+1 | image { (sin(x, y), 0, 0) }
+  |          ^^^^^^^^^
+  = hint: expected sin(float)
+");
+    }
+
+    #[test]
+    fn multiline_spans() {
+        assert_eq!(render("image {\n    c = (x, y)\n\t    + (x, y, t);\n    c\n}"), "\
+error: cannot apply `+` to vec2 and vec3
+ --> test.shy:2:9
+  |
+2 |     c = (x, y)
+  |         ^^^^^^
+3 | \t    + (x, y, t);
+  | \t    ^^^^^^^^^^^
+  = hint: `+` takes two operands of the same type, or a float and a vector
+");
+    }
+
+    #[test]
+    fn parse_errors() {
+        // The exact set of expected tokens depends on the grammar's tables
+        let out = render("image {\n    c = (x, y, 0)\n    c\n}");
+        assert!(out.starts_with("error: unexpected `c`\n --> test.shy:3:5\n  |\n3 |     c\n  |     ^\n  = hint: expected one of "), "{}", out);
+        assert!(out.contains("`;`"), "{}", out);
+    }
+}
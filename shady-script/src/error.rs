@@ -0,0 +1,216 @@
+use std::error;
+use std::fmt;
+
+use lalrpop_util;
+
+use analyse::AnalyseError;
+use lexer::{Tok, LexError, LexErrorKind};
+use diagnostic::{Diagnostic, Level};
+use lint::Warning;
+use span::Span;
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    pub span: Span,
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ParseErrorKind {
+    InvalidToken,
+    // The token found, and the tokens that were expected instead
+    UnrecognizedToken(String, Vec<String>),
+    UnexpectedEnd(Vec<String>),
+    ExtraToken(String),
+    UnexpectedCharacter(char),
+    UnterminatedComment,
+    MissingExponent,
+    NumberOutOfRange,
+}
+
+pub(crate) type RawParseError<'a> = lalrpop_util::ParseError<usize, Tok<'a>, LexError>;
+
+impl ParseError {
+    // Copies what is needed out of the parser's error, which borrows from
+    // the input. `input` is used to locate errors at the end of the script.
+    pub(crate) fn new(err: RawParseError, input: &str) -> ParseError {
+        let eof = Span { begin: input.len(), end: input.len() };
+
+        let (kind, span) = match err {
+            // The lexer's errors reach the parser as tokens
+            lalrpop_util::ParseError::UnrecognizedToken { token: Some((begin, Tok::Error(kind), end)), .. } =>
+                (lex_error_kind(kind), Span { begin: begin, end: end }),
+
+
+            lalrpop_util::ParseError::InvalidToken { location } =>
+                (ParseErrorKind::InvalidToken, Span { begin: location, end: location + 1 }),
+
+            lalrpop_util::ParseError::UnrecognizedToken { token: Some((begin, tok, end)), expected } =>
+                (ParseErrorKind::UnrecognizedToken(tok.to_string(), expected), Span { begin: begin, end: end }),
+
+            lalrpop_util::ParseError::UnrecognizedToken { token: None, expected } =>
+                (ParseErrorKind::UnexpectedEnd(expected), eof),
+
+            lalrpop_util::ParseError::ExtraToken { token: (begin, tok, end) } =>
+                (ParseErrorKind::ExtraToken(tok.to_string()), Span { begin: begin, end: end }),
+
+            lalrpop_util::ParseError::User { error } => (lex_error_kind(error.kind), error.span),
+        };
+
+        ParseError {
+            kind: kind,
+            span: span,
+            file: None,
+        }
+    }
+}
+
+fn lex_error_kind(kind: LexErrorKind) -> ParseErrorKind {
+    match kind {
+        LexErrorKind::UnexpectedCharacter(c) => ParseErrorKind::UnexpectedCharacter(c),
+        LexErrorKind::UnterminatedComment => ParseErrorKind::UnterminatedComment,
+        LexErrorKind::MissingExponent => ParseErrorKind::MissingExponent,
+        LexErrorKind::NumberOutOfRange => ParseErrorKind::NumberOutOfRange,
+    }
+}
+
+// Errors on their own only know byte offsets; CompileError keeps the source,
+// and shows lines and columns instead.
+pub(crate) fn write_located(f: &mut fmt::Formatter, file: &Option<String>, span: Span, message: &str) -> fmt::Result {
+    match *file {
+        Some(ref file) => write!(f, "{}:{}..{}: {}", file, span.begin, span.end, message),
+        None => write!(f, "{}..{}: {}", span.begin, span.end, message),
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_located(f, &self.file, self.span, &self.diagnostic().message)
+    }
+}
+
+impl error::Error for ParseError {
+    fn description(&self) -> &str {
+        "syntax error"
+    }
+}
+
+impl fmt::Display for AnalyseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_located(f, &self.file, self.span, &self.diagnostic().message)
+    }
+}
+
+impl error::Error for AnalyseError {
+    fn description(&self) -> &str {
+        "type error"
+    }
+}
+
+// Everything that went wrong compiling a script, along with the script itself
+// so the errors can be shown in context.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct CompileError {
+    pub file: Option<String>,
+    pub source: String,
+    pub parse_errors: Vec<ParseError>,
+    pub analyse_errors: Vec<AnalyseError>,
+    // Warnings treated as errors
+    pub warnings: Vec<Warning>,
+}
+
+impl CompileError {
+    pub fn from_parse(source: &str, errors: Vec<ParseError>) -> CompileError {
+        CompileError {
+            file: None,
+            source: source.to_owned(),
+            parse_errors: errors,
+            analyse_errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn from_analyse(source: &str, errors: Vec<AnalyseError>) -> CompileError {
+        CompileError {
+            file: None,
+            source: source.to_owned(),
+            parse_errors: Vec::new(),
+            analyse_errors: errors,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn from_warnings(source: &str, warnings: Vec<Warning>) -> CompileError {
+        CompileError {
+            file: None,
+            source: source.to_owned(),
+            parse_errors: Vec::new(),
+            analyse_errors: Vec::new(),
+            warnings: warnings,
+        }
+    }
+
+    // Names the file the script came from, in this and every contained error
+    pub fn with_file<S: Into<String>>(mut self, file: S) -> CompileError {
+        let file = file.into();
+
+        for err in &mut self.parse_errors {
+            err.file = Some(file.clone());
+        }
+
+        for err in &mut self.analyse_errors {
+            err.file = Some(file.clone());
+        }
+
+        for warning in &mut self.warnings {
+            warning.file = Some(file.clone());
+        }
+
+        self.file = Some(file);
+        self
+    }
+
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.parse_errors.iter().map(ParseError::diagnostic)
+            .chain(self.analyse_errors.iter().map(AnalyseError::diagnostic))
+            .chain(self.warnings.iter().map(|warning| Diagnostic { level: Level::Error, ..warning.diagnostic() }))
+            .collect()
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = self.file.as_ref().map_or("<script>", |file| &file[..]);
+        let rendered: Vec<String> = self.diagnostics().iter().map(|d| d.render(name, &self.source)).collect();
+
+        f.write_str(rendered.join("\n").trim_right())
+    }
+}
+
+impl error::Error for CompileError {
+    fn description(&self) -> &str {
+        "failed to compile script"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::thread;
+
+    #[test]
+    fn owned_errors() {
+        let err = {
+            let src = String::from("image {\n    (x, y, z)\n}");
+            ::compile(&src).unwrap_err().with_file("test.shy")
+        };
+
+        // Errors outlive the source and can be sent between threads
+        let err = thread::spawn(move || err).join().unwrap();
+
+        assert_eq!(err.analyse_errors[0].to_string(), "test.shy:19..20: undefined name `z`");
+        assert!(err.to_string().starts_with("error: undefined name `z`\n --> test.shy:2:12\n"));
+        assert_eq!(err.description(), "failed to compile script");
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use ::{ast, instr};
+use functions::lookup_function;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Value {
+    Void,
+    Float(f32),
+    Bool(bool),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Inputs {
+    pub x: f32,
+    pub y: f32,
+    pub t: f32,
+    pub mx: f32,
+    pub my: f32,
+}
+
+pub trait FromValue {
+    fn from_value(value: Value) -> Self;
+}
+
+impl FromValue for f32 {
+    fn from_value(value: Value) -> f32 {
+        match value {
+            Value::Float(f) => f,
+            v => panic!("Expected float, found {:?}", v)
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> bool {
+        match value {
+            Value::Bool(b) => b,
+            v => panic!("Expected bool, found {:?}", v)
+        }
+    }
+}
+
+impl FromValue for [f32; 2] {
+    fn from_value(value: Value) -> [f32; 2] {
+        match value {
+            Value::Vec2(v) => v,
+            v => panic!("Expected vec2, found {:?}", v)
+        }
+    }
+}
+
+impl FromValue for [f32; 3] {
+    fn from_value(value: Value) -> [f32; 3] {
+        match value {
+            Value::Vec3(v) => v,
+            v => panic!("Expected vec3, found {:?}", v)
+        }
+    }
+}
+
+impl Value {
+    pub fn zero(ty: instr::Type) -> Value {
+        match ty {
+            instr::Type::Void | instr::Type::Error => Value::Void,
+            instr::Type::Float => Value::Float(0.0),
+            instr::Type::Bool => Value::Bool(false),
+            instr::Type::Vec2 => Value::Vec2([0.0; 2]),
+            instr::Type::Vec3 => Value::Vec3([0.0; 3]),
+        }
+    }
+
+    pub fn ty(&self) -> instr::Type {
+        match *self {
+            Value::Void => instr::Type::Void,
+            Value::Float(_) => instr::Type::Float,
+            Value::Bool(_) => instr::Type::Bool,
+            Value::Vec2(_) => instr::Type::Vec2,
+            Value::Vec3(_) => instr::Type::Vec3,
+        }
+    }
+
+    pub fn get<T: FromValue>(self) -> T {
+        T::from_value(self)
+    }
+
+    // Vector constructors accept bools as well as floats, as GLSL does.
+    fn component(self) -> f32 {
+        match self {
+            Value::Bool(b) => if b { 1.0 } else { 0.0 },
+            v => v.get()
+        }
+    }
+
+    // Applies an arithmetic operator the way GLSL does: component-wise, with
+    // a float operand broadcast across the other operand's components.
+    pub fn arith(op: ast::ArithOpKind, a: Value, b: Value) -> Value {
+        let f = |x: f32, y: f32| match op {
+            ast::ArithOpKind::Add => x + y,
+            ast::ArithOpKind::Sub => x - y,
+            ast::ArithOpKind::Mul => x * y,
+            ast::ArithOpKind::Div => x / y,
+            ast::ArithOpKind::Mod => modulo(x, y),
+            ast::ArithOpKind::Pow => x.powf(y),
+        };
+
+        match (a, b) {
+            (Value::Float(x), Value::Float(y)) => Value::Float(f(x, y)),
+            (Value::Float(x), Value::Vec2(v)) => Value::Vec2([f(x, v[0]), f(x, v[1])]),
+            (Value::Vec2(v), Value::Float(y)) => Value::Vec2([f(v[0], y), f(v[1], y)]),
+            (Value::Vec2(u), Value::Vec2(v)) => Value::Vec2([f(u[0], v[0]), f(u[1], v[1])]),
+            (Value::Float(x), Value::Vec3(v)) => Value::Vec3([f(x, v[0]), f(x, v[1]), f(x, v[2])]),
+            (Value::Vec3(v), Value::Float(y)) => Value::Vec3([f(v[0], y), f(v[1], y), f(v[2], y)]),
+            (Value::Vec3(u), Value::Vec3(v)) => Value::Vec3([f(u[0], v[0]), f(u[1], v[1]), f(u[2], v[2])]),
+            (a, b) => panic!("Invalid operands for {:?}: {:?}, {:?}", op, a, b)
+        }
+    }
+
+    pub fn neg(self) -> Value {
+        match self {
+            Value::Float(x) => Value::Float(-x),
+            Value::Vec2(v) => Value::Vec2([-v[0], -v[1]]),
+            Value::Vec3(v) => Value::Vec3([-v[0], -v[1], -v[2]]),
+            v => panic!("Invalid operand for negation: {:?}", v)
+        }
+    }
+
+    // Vectors are equal when every component is, and unequal if any
+    // component differs. Only floats are ordered, as in GLSL.
+    pub fn cmp(op: ast::CmpOpKind, a: Value, b: Value) -> Value {
+        let pairs = match (a, b) {
+            (Value::Float(x), Value::Float(y)) => vec![(x, y)],
+            (Value::Bool(_), Value::Bool(_)) => vec![(a.component(), b.component())],
+            (Value::Vec2(u), Value::Vec2(v)) => vec![(u[0], v[0]), (u[1], v[1])],
+            (Value::Vec3(u), Value::Vec3(v)) => vec![(u[0], v[0]), (u[1], v[1]), (u[2], v[2])],
+            (a, b) => panic!("Invalid operands for {:?}: {:?}, {:?}", op, a, b)
+        };
+
+        Value::Bool(match op {
+            ast::CmpOpKind::Ne => pairs.iter().any(|&(x, y)| x != y),
+            op => pairs.iter().all(|&(x, y)| compare(op, x, y)),
+        })
+    }
+}
+
+struct Env<'a> {
+    inputs: &'a Inputs,
+    names: HashMap<&'a str, Value>,
+}
+
+impl<'a> Env<'a> {
+    fn new(inputs: &'a Inputs) -> Env<'a> {
+        Env {
+            inputs: inputs,
+            names: HashMap::new()
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Value {
+        match self.names.get(name) {
+            Some(value) => *value,
+            None => panic!("Undefined name {}", name)
+        }
+    }
+
+    fn insert(&mut self, name: &'a str, value: Value) {
+        self.names.insert(name, value);
+    }
+}
+
+impl instr::Item {
+    pub fn eval(&self, inputs: &Inputs) -> Value {
+        let mut env = Env::new(inputs);
+
+        match eval_instrs(&mut env, &self.instrs) {
+            Some(value) => value,
+
+            // GLSL leaves the result undefined; zero is as good as anything
+            None => Value::zero(self.ret)
+        }
+    }
+}
+
+fn eval_instrs<'a>(env: &mut Env<'a>, instrs: &'a [instr::Instr]) -> Option<Value> {
+    for instr in instrs {
+        match *instr {
+            instr::Instr::Decl(ref name, ty, ref expr) => {
+                let value = match *expr {
+                    Some(ref expr) => eval_expr(env, expr),
+                    None => Value::zero(ty)
+                };
+
+                env.insert(name, value);
+            },
+
+            instr::Instr::Assignment(ref name, ref expr) => {
+                let value = eval_expr(env, &expr.expr);
+                env.insert(name, value);
+            },
+
+            instr::Instr::Return(ref expr) => return Some(eval_expr(env, &expr.expr)),
+
+            instr::Instr::ITE(ref cond, ref t, ref e) => {
+                let ret = if eval_expr(env, cond).get() {
+                    eval_instrs(env, &t.instrs)
+                } else if let Some(ref e) = *e {
+                    eval_instrs(env, &e.instrs)
+                } else {
+                    None
+                };
+
+                if ret.is_some() {
+                    return ret
+                }
+            },
+        }
+    }
+
+    None
+}
+
+fn eval_expr(env: &Env, expr: &instr::ExprKind) -> Value {
+    match *expr {
+        instr::ExprKind::KeyVar(var) => Value::Float(match var {
+            ast::KeyVar::XPos => env.inputs.x,
+            ast::KeyVar::YPos => env.inputs.y,
+            ast::KeyVar::Time => env.inputs.t,
+            ast::KeyVar::MouseX => env.inputs.mx,
+            ast::KeyVar::MouseY => env.inputs.my,
+        }),
+
+        instr::ExprKind::Literal(lit) => Value::Float(lit as f32),
+        instr::ExprKind::Bool(b) => Value::Bool(b),
+        instr::ExprKind::Var(ref name) => env.lookup(name),
+
+        instr::ExprKind::Application(ref name, ref exprs) => {
+            let args: Vec<Value> = exprs.iter().map(|expr| eval_expr(env, expr)).collect();
+            call_function(name, &args)
+        },
+
+        instr::ExprKind::Vec2(ref exprs) => Value::Vec2([
+            eval_expr(env, &exprs.0).component(),
+            eval_expr(env, &exprs.1).component()
+        ]),
+
+        instr::ExprKind::Vec3(ref exprs) => Value::Vec3([
+            eval_expr(env, &exprs.0).component(),
+            eval_expr(env, &exprs.1).component(),
+            eval_expr(env, &exprs.2).component()
+        ]),
+
+        instr::ExprKind::BinOp(ast::OpKind::ArithOp(op), ref exprs) => Value::arith(op, eval_expr(env, &exprs.0), eval_expr(env, &exprs.1)),
+        instr::ExprKind::BinOp(ast::OpKind::CmpOp(op), ref exprs) => Value::cmp(op, eval_expr(env, &exprs.0), eval_expr(env, &exprs.1)),
+        // `&&` and `||` leave the right operand unevaluated if they can
+        instr::ExprKind::BinOp(ast::OpKind::LogicOp(op), ref exprs) => {
+            let lhs = bool::from_value(eval_expr(env, &exprs.0));
+
+            Value::Bool(match op {
+                ast::LogicOpKind::And => lhs && bool::from_value(eval_expr(env, &exprs.1)),
+                ast::LogicOpKind::Or => lhs || bool::from_value(eval_expr(env, &exprs.1)),
+            })
+        },
+
+        instr::ExprKind::UnOp(ast::UnOpKind::Neg, ref expr) => eval_expr(env, expr).neg(),
+        instr::ExprKind::UnOp(ast::UnOpKind::Not, ref expr) => Value::Bool(!bool::from_value(eval_expr(env, expr))),
+    }
+}
+
+pub(crate) fn compare(op: ast::CmpOpKind, x: f32, y: f32) -> bool {
+    match op {
+        ast::CmpOpKind::Lt => x < y,
+        ast::CmpOpKind::Gt => x > y,
+        ast::CmpOpKind::Eq => x == y,
+        ast::CmpOpKind::Le => x <= y,
+        ast::CmpOpKind::Ge => x >= y,
+        ast::CmpOpKind::Ne => x != y,
+    }
+}
+
+// GLSL's `mod`, which rounds the quotient down rather than towards zero
+pub(crate) fn modulo(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+fn call_function(name: &str, args: &[Value]) -> Value {
+    let tys: Vec<instr::Type> = args.iter().map(Value::ty).collect();
+
+    match lookup_function(name, &tys) {
+        Some(f) => (f.eval)(args),
+        None => panic!("No function {} matching {:?}", name, tys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Inputs, Value};
+
+    fn eval(src: &str, inputs: Inputs) -> Value {
+        let sdy = ::parse_input(src).unwrap().analyse().unwrap();
+        sdy.get(0).eval(&inputs)
+    }
+
+    #[test]
+    fn keyvars_and_builtins() {
+        let inputs = Inputs { x: 0.25, y: 0.5, t: 2.0, mx: 0.0, my: 1.0 };
+        let v = eval("image { (x, max(y, my), sin(t)) }", inputs);
+        assert_eq!(v, Value::Vec3([0.25, 1.0, 2.0f32.sin()]));
+    }
+
+    #[test]
+    fn broadcast_arithmetic() {
+        let v = eval("image { c = (1, 2, 3); c * 2 - 1 }", Inputs::default());
+        assert_eq!(v, Value::Vec3([1.0, 3.0, 5.0]));
+    }
+
+    #[test]
+    fn branches_and_returns() {
+        let src = "image { c = (0, 0, 0); if x < 0.5 { c = (1, 0, 0); } else { return (0, 0, 1); }; c }";
+        assert_eq!(eval(src, Inputs { x: 0.25, ..Inputs::default() }), Value::Vec3([1.0, 0.0, 0.0]));
+        assert_eq!(eval(src, Inputs { x: 0.75, ..Inputs::default() }), Value::Vec3([0.0, 0.0, 1.0]));
+    }
+}
@@ -1,37 +1,54 @@
 use instr::Type;
+use eval::Value;
 
 pub struct Function {
     pub name: &'static str,
     pub args: &'static [Type],
     pub ret: Type,
+    pub eval: fn(&[Value]) -> Value,
 }
 
 macro_rules! functions {
-    ($($name:ident($($arg:ident),*) -> $ret:ident;)+) => {
-        static FUNCTIONS: &'static [Function] = &[
+    ($($name:ident($($arg:ident: $ty:ident),*) -> $ret:ident = $body:expr;)+) => {
+        pub static FUNCTIONS: &'static [Function] = &[
             $(Function {
                 name: stringify!($name),
-                args: &[$(Type::$arg),*],
-                ret: Type::$ret
+                args: &[$(Type::$ty),*],
+                ret: Type::$ret,
+                eval: {
+                    #[allow(unused_mut, unused_variables)]
+                    fn eval(args: &[Value]) -> Value {
+                        let mut args = args.iter();
+                        $(let $arg = args.next().unwrap().get::<value_type!($ty)>();)*
+                        Value::$ret($body)
+                    }
+
+                    eval
+                }
             }),+
         ];
     };
 }
 
+macro_rules! value_type {
+    (Float) => (f32);
+    (Bool) => (bool);
+    (Vec2) => ([f32; 2]);
+    (Vec3) => ([f32; 3]);
+}
+
 functions! {
-    sin(Float) -> Float;
-    cos(Float) -> Float;
-    tan(Float) -> Float;
-    min(Float, Float) -> Float;
-    max(Float, Float) -> Float;
+    sin(a: Float) -> Float = a.sin();
+    cos(a: Float) -> Float = a.cos();
+    tan(a: Float) -> Float = a.tan();
+    min(a: Float, b: Float) -> Float = a.min(b);
+    max(a: Float, b: Float) -> Float = a.max(b);
 }
 
-pub fn find_function(name: &str, args: &[Type]) -> Option<Type> {
-    for f in FUNCTIONS {
-        if f.name == name && f.args == args {
-            return Some(f.ret)
-        }
-    }
+pub fn lookup_function(name: &str, args: &[Type]) -> Option<&'static Function> {
+    FUNCTIONS.iter().find(|f| f.name == name && f.args == args)
+}
 
-    None
+pub fn find_function(name: &str, args: &[Type]) -> Option<Type> {
+    lookup_function(name, args).map(|f| f.ret)
 }
@@ -1,6 +1,8 @@
 use std::fmt::{self, Write};
 
-use ::{ast, instr};
+use ::{ast, instr, pretty};
+use eval::Inputs;
+use vm::Program;
 
 pub struct Image<'a>(&'a ::Shady, usize);
 
@@ -25,6 +27,18 @@ impl<'a> Image<'a> {
         }).collect()
     }
 
+    pub(crate) fn item(&self) -> &'a instr::Item {
+        self.0.get(self.1)
+    }
+
+    pub fn eval(&self, inputs: &Inputs) -> [f32; 3] {
+        self.item().eval(inputs).get()
+    }
+
+    pub fn program(&self) -> Program {
+        Program::compile(self.item())
+    }
+
     pub fn standalone_shader(&self) -> String {
         let mut uniform_buffer = String::new();
         for uniform in self.standalone_uniforms().iter() {
@@ -142,32 +156,213 @@ impl fmt::Display for instr::Type {
             &instr::Type::Float => write!(f, "float"),
             &instr::Type::Vec2 => write!(f, "vec2"),
             &instr::Type::Vec3 => write!(f, "vec3"),
+            &instr::Type::Error => write!(f, "{{error}}"),
         }
     }
 }
 
-impl fmt::Display for instr::ExprKind {
+// GLSL reads `1` as an int, so every float is written with a point or exponent
+struct Float(f64);
+
+impl fmt::Display for Float {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        match self {
-            &instr::ExprKind::KeyVar(ast::KeyVar::XPos) => write!(f, "x"),
-            &instr::ExprKind::KeyVar(ast::KeyVar::YPos) => write!(f, "y"),
-            &instr::ExprKind::KeyVar(ast::KeyVar::Time) => write!(f, "t"),
-            &instr::ExprKind::KeyVar(ast::KeyVar::MouseX) => write!(f, "mx"),
-            &instr::ExprKind::KeyVar(ast::KeyVar::MouseY) => write!(f, "my"),
-            &instr::ExprKind::Literal(ref s) => write!(f, "{}", s),
-            &instr::ExprKind::Bool(ref b) => write!(f, "{}", b),
-            &instr::ExprKind::Var(ref s) => write!(f, "{}", s),
-            &instr::ExprKind::Application(ref name, ref exprs) => write!(f, "{}({})", name, ExprVec(exprs)),
-            &instr::ExprKind::Vec2(ref exprs) => write!(f, "vec2({}, {})", exprs.0, exprs.1),
-            &instr::ExprKind::Vec3(ref exprs) => write!(f, "vec3({}, {}, {})", exprs.0, exprs.1, exprs.2),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Add), ref exprs) => write!(f, "{} + {}", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Sub), ref exprs) => write!(f, "{} - {}", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Mul), ref exprs) => write!(f, "({}) * ({})", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Div), ref exprs) => write!(f, "({}) / ({})", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Lt), ref exprs) => write!(f, "({}) < ({})", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Gt), ref exprs) => write!(f, "({}) > ({})", exprs.0, exprs.1),
-            &instr::ExprKind::BinOp(ast::OpKind::CmpOp(ast::CmpOpKind::Eq), ref exprs) => write!(f, "({}) == ({})", exprs.0, exprs.1),
+        let text = format!("{:?}", self.0 as f32);
+
+        if text.contains('.') || text.contains('e') {
+            write!(f, "{}", text)
+        } else {
+            write!(f, "{}.0", text)
         }
     }
 }
 
+impl fmt::Display for instr::ExprKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write_expr(f, self, 0)
+    }
+}
+
+// How tightly each operator binds, from loosest to tightest. This matches
+// both GLSL and the script grammar.
+fn precedence(op: ast::OpKind) -> u8 {
+    match op {
+        ast::OpKind::LogicOp(ast::LogicOpKind::Or) => 1,
+        ast::OpKind::LogicOp(ast::LogicOpKind::And) => 2,
+        ast::OpKind::CmpOp(ast::CmpOpKind::Eq) | ast::OpKind::CmpOp(ast::CmpOpKind::Ne) => 3,
+        ast::OpKind::CmpOp(_) => 4,
+        ast::OpKind::ArithOp(ast::ArithOpKind::Add) | ast::OpKind::ArithOp(ast::ArithOpKind::Sub) => 5,
+        ast::OpKind::ArithOp(ast::ArithOpKind::Mul) | ast::OpKind::ArithOp(ast::ArithOpKind::Div) |
+        ast::OpKind::ArithOp(ast::ArithOpKind::Mod) => 6,
+        ast::OpKind::ArithOp(ast::ArithOpKind::Pow) => 8,
+    }
+}
+
+// Unary operators bind tighter than any binary operator but `^`
+const UNARY: u8 = 7;
+
+// Writes an expression, parenthesised only if it binds more loosely than the
+// `min` precedence its position needs
+fn write_expr(f: &mut fmt::Formatter, expr: &instr::ExprKind, min: u8) -> Result<(), fmt::Error> {
+    match *expr {
+        instr::ExprKind::KeyVar(ast::KeyVar::XPos) => write!(f, "x"),
+        instr::ExprKind::KeyVar(ast::KeyVar::YPos) => write!(f, "y"),
+        instr::ExprKind::KeyVar(ast::KeyVar::Time) => write!(f, "t"),
+        instr::ExprKind::KeyVar(ast::KeyVar::MouseX) => write!(f, "mx"),
+        instr::ExprKind::KeyVar(ast::KeyVar::MouseY) => write!(f, "my"),
+        instr::ExprKind::Literal(lit) => write!(f, "{}", Float(lit)),
+        instr::ExprKind::Bool(b) => write!(f, "{}", b),
+        instr::ExprKind::Var(ref s) => write!(f, "{}", s),
+        instr::ExprKind::Application(ref name, ref exprs) => write!(f, "{}({})", name, ExprVec(exprs)),
+        instr::ExprKind::Vec2(ref exprs) => write!(f, "vec2({}, {})", exprs.0, exprs.1),
+        instr::ExprKind::Vec3(ref exprs) => write!(f, "vec3({}, {}, {})", exprs.0, exprs.1, exprs.2),
+
+        // GLSL only has functions for these, and its `%` is for integers
+        instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Mod), ref exprs) => write!(f, "mod({}, {})", exprs.0, exprs.1),
+        instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Pow), ref exprs) => write!(f, "pow({}, {})", exprs.0, exprs.1),
+
+        instr::ExprKind::BinOp(kind, ref exprs) => {
+            let prec = precedence(kind);
+
+            if prec < min {
+                try!(write!(f, "("));
+            }
+
+            // Operators are left associative, so only the right operand needs
+            // parentheses at the same precedence
+            try!(write_expr(f, &exprs.0, prec));
+            try!(write!(f, " {} ", pretty::op(kind)));
+            try!(write_expr(f, &exprs.1, prec + 1));
+
+            if prec < min {
+                try!(write!(f, ")"));
+            }
+
+            Ok(())
+        },
+
+        instr::ExprKind::UnOp(kind, ref expr) => {
+            if UNARY < min {
+                try!(write!(f, "("));
+            }
+
+            // Nested unary operators are parenthesised, since `--` is decrement
+            try!(write!(f, "{}", pretty::unop(kind)));
+            try!(write_expr(f, expr, UNARY + 1));
+
+            if UNARY < min {
+                try!(write!(f, ")"));
+            }
+
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::{ast, instr};
+
+    #[test]
+    fn float_literals() {
+        let sdy = ::compile("image { (1 / 2, .5, 2e3 + 1e-7) }").unwrap();
+        let shader = super::Image::new(&sdy, 0).standalone_shader();
+
+        assert!(shader.contains("vec3(1.0 / 2.0, 0.5, 2000.0 + 1e-7)"), "{}", shader);
+    }
+
+    // Converts a parsed expression back into the IR, reading the GLSL
+    // constructors as vectors
+    fn lower(expr: &ast::Expr) -> instr::ExprKind {
+        match *expr {
+            ast::Expr::Var(ref name) => instr::ExprKind::Var(name.clone()),
+            ast::Expr::Literal(lit) => instr::ExprKind::Literal(lit),
+
+            ast::Expr::App(ref name, ref exprs) => {
+                let exprs: Vec<instr::ExprKind> = exprs.iter().map(|expr| lower(&expr.data)).collect();
+
+                match &name[..] {
+                    "mod" => instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Mod), Box::new((exprs[0].clone(), exprs[1].clone()))),
+                    "pow" => instr::ExprKind::BinOp(ast::OpKind::ArithOp(ast::ArithOpKind::Pow), Box::new((exprs[0].clone(), exprs[1].clone()))),
+                    "vec2" => instr::ExprKind::Vec2(Box::new((exprs[0].clone(), exprs[1].clone()))),
+                    "vec3" => instr::ExprKind::Vec3(Box::new((exprs[0].clone(), exprs[1].clone(), exprs[2].clone()))),
+                    _ => instr::ExprKind::Application(name.clone(), exprs),
+                }
+            },
+
+            ast::Expr::BinOp(kind, ref exprs) => instr::ExprKind::BinOp(kind, Box::new((lower(&exprs.0.data), lower(&exprs.1.data)))),
+            ast::Expr::UnOp(kind, ref expr) => instr::ExprKind::UnOp(kind, Box::new(lower(&expr.data))),
+
+            _ => panic!("unexpected expression {:?}", expr),
+        }
+    }
+
+    fn parse(src: &str) -> instr::ExprKind {
+        let ast = ::parse_input(&format!("image {{ {} }}", src)).unwrap();
+        lower(&ast.items[0].data.block.data.expr.as_ref().unwrap().data)
+    }
+
+    fn round_trip(expr: &instr::ExprKind) -> instr::ExprKind {
+        parse(&expr.to_string())
+    }
+
+    // Every tree of binary operators with the given number of leaves, where
+    // each operand may have a unary operator applied
+    fn trees(leaves: usize) -> Vec<instr::ExprKind> {
+        let unary = |op, expr: &instr::ExprKind| instr::ExprKind::UnOp(op, Box::new(expr.clone()));
+
+        if leaves == 1 {
+            let var = instr::ExprKind::Var("a".to_owned());
+            return vec![unary(ast::UnOpKind::Neg, &var), var, instr::ExprKind::Literal(2.0)]
+        }
+
+        let ops = [
+            ast::OpKind::ArithOp(ast::ArithOpKind::Add), ast::OpKind::ArithOp(ast::ArithOpKind::Sub),
+            ast::OpKind::ArithOp(ast::ArithOpKind::Mul), ast::OpKind::ArithOp(ast::ArithOpKind::Div),
+            ast::OpKind::ArithOp(ast::ArithOpKind::Mod), ast::OpKind::ArithOp(ast::ArithOpKind::Pow),
+            ast::OpKind::CmpOp(ast::CmpOpKind::Lt), ast::OpKind::CmpOp(ast::CmpOpKind::Eq),
+            ast::OpKind::CmpOp(ast::CmpOpKind::Ge), ast::OpKind::CmpOp(ast::CmpOpKind::Ne),
+            ast::OpKind::LogicOp(ast::LogicOpKind::And), ast::OpKind::LogicOp(ast::LogicOpKind::Or),
+        ];
+        let mut all = Vec::new();
+
+        for split in 1..leaves {
+            for lhs in &trees(split) {
+                for rhs in &trees(leaves - split) {
+                    for &op in &ops {
+                        let expr = instr::ExprKind::BinOp(op, Box::new((lhs.clone(), rhs.clone())));
+                        let un = if let ast::OpKind::ArithOp(_) = op { ast::UnOpKind::Neg } else { ast::UnOpKind::Not };
+
+                        all.push(unary(un, &expr));
+                        all.push(expr);
+                    }
+                }
+            }
+        }
+
+        all
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        assert_eq!(parse("a - (b + c)").to_string(), "a - (b + c)");
+        assert_eq!(parse("((a * b)) + (c / 2)").to_string(), "a * b + c / 2.0");
+        assert_eq!(parse("-(-a) * -(b + c) ^ 2 % 3").to_string(), "mod(-(-a) * -pow(b + c, 2.0), 3.0)");
+        assert_eq!(parse("a < b + c == (d > e)").to_string(), "a < b + c == d > e");
+        assert_eq!(parse("(a || b) && !(c != d) || e <= f").to_string(), "(a || b) && !(c != d) || e <= f");
+
+        let expr = parse("vec3(a * (b - 1), f((a + b) / c, a - b - c), a / (b * c))");
+        assert_eq!(expr.to_string(), "vec3(a * (b - 1.0), f((a + b) / c, a - b - c), a / (b * c))");
+    }
+
+    #[test]
+    fn round_trips() {
+        for leaves in 1..4 {
+            for expr in trees(leaves) {
+                assert_eq!(round_trip(&expr), expr, "{}", expr);
+            }
+        }
+
+        let vector = instr::ExprKind::Vec2(Box::new((trees(3).remove(7), trees(2).remove(5))));
+        assert_eq!(round_trip(&vector), vector);
+    }
+}
@@ -1,13 +1,16 @@
 use ast;
+use span::Span;
 use std::collections::BTreeSet;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Block {
     pub ret: Option<Type>,
     pub instrs: Vec<Instr>,
+    // The source block, for reporting paths that don't return
+    pub span: Span,
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Item {
     pub ret: Type,
     pub kind: ast::ItemKind,
@@ -15,7 +18,7 @@ pub struct Item {
     pub vars: BTreeSet<ast::KeyVar>
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Instr {
     Decl(String, Type, Option<ExprKind>),
     Assignment(String, Expr),
@@ -23,22 +26,34 @@ pub enum Instr {
     ITE(ExprKind, Block, Option<Block>),
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Expr {
     pub ty: Type,
     pub expr: ExprKind
 }
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+impl Expr {
+    // Stands in for an expression that failed to analyse. It never reaches
+    // code generation, since analysis fails with the recorded errors.
+    pub fn error() -> Expr {
+        Expr {
+            ty: Type::Error,
+            expr: ExprKind::Literal(0.0)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum ExprKind {
     KeyVar(ast::KeyVar),
-    Literal(String),
+    Literal(f64),
     Bool(bool),
     Var(String),
     Application(String, Vec<ExprKind>),
     Vec2(Box<(ExprKind, ExprKind)>),
     Vec3(Box<(ExprKind, ExprKind, ExprKind)>),
     BinOp(ast::OpKind, Box<(ExprKind, ExprKind)>),
+    UnOp(ast::UnOpKind, Box<ExprKind>),
 }
 
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
@@ -47,5 +62,7 @@ pub enum Type {
     Float,
     Bool,
     Vec2,
-    Vec3
+    Vec3,
+    // Given to expressions that failed to analyse
+    Error
 }
@@ -0,0 +1,383 @@
+use std::f32;
+use std::fmt;
+use std::str::CharIndices;
+use std::iter::Peekable;
+
+use span::Span;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Tok<'a> {
+    Image,
+    Return,
+    If,
+    Else,
+    True,
+    False,
+    Allow,
+    X,
+    Y,
+    T,
+    MouseX,
+    MouseY,
+    Name(&'a str),
+    Number(&'a str),
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Semi,
+    Assign,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    Caret,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    EqEq,
+    NotEq,
+    AndAnd,
+    OrOr,
+    Bang,
+
+    // Text that isn't a token, passed on so the parser can skip past it
+    Error(LexErrorKind),
+}
+
+impl<'a> fmt::Display for Tok<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match *self {
+            Tok::Image => "image",
+            Tok::Return => "return",
+            Tok::If => "if",
+            Tok::Else => "else",
+            Tok::True => "true",
+            Tok::False => "false",
+            Tok::Allow => "allow",
+            Tok::X => "x",
+            Tok::Y => "y",
+            Tok::T => "t",
+            Tok::MouseX => "mx",
+            Tok::MouseY => "my",
+            Tok::Name(name) => name,
+            Tok::Number(num) => num,
+
+            Tok::LParen => "(",
+            Tok::RParen => ")",
+            Tok::LBrace => "{",
+            Tok::RBrace => "}",
+            Tok::Comma => ",",
+            Tok::Semi => ";",
+            Tok::Assign => "=",
+            Tok::Plus => "+",
+            Tok::Minus => "-",
+            Tok::Star => "*",
+            Tok::Slash => "/",
+            Tok::Percent => "%",
+            Tok::Caret => "^",
+            Tok::Lt => "<",
+            Tok::Gt => ">",
+            Tok::LtEq => "<=",
+            Tok::GtEq => ">=",
+            Tok::EqEq => "==",
+            Tok::NotEq => "!=",
+            Tok::AndAnd => "&&",
+            Tok::OrOr => "||",
+            Tok::Bang => "!",
+
+            Tok::Error(_) => "invalid token",
+        };
+
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedComment,
+    // A number like `1e` or `2e+`
+    MissingExponent,
+    // A number too large to be a 32-bit float
+    NumberOutOfRange,
+}
+
+pub type Spanned<'a> = Result<(usize, Tok<'a>, usize), LexError>;
+
+pub struct Lexer<'a> {
+    input: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str) -> Lexer<'a> {
+        Lexer {
+            input: input,
+            chars: input.char_indices().peekable(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().map(|&(_, c)| c)
+    }
+
+    // The offset of the next character, or the end of the input
+    fn offset(&mut self) -> usize {
+        let len = self.input.len();
+        self.chars.peek().map_or(len, |&(idx, _)| idx)
+    }
+
+    fn take_while<F: Fn(char) -> bool>(&mut self, f: F) -> usize {
+        while self.peek().map_or(false, &f) {
+            self.chars.next();
+        }
+
+        self.offset()
+    }
+
+    // Skips whitespace and comments, up to the start of the next token
+    fn skip(&mut self) -> Result<(), LexError> {
+        loop {
+            self.take_while(char::is_whitespace);
+
+            let start = self.offset();
+            if !self.input[start..].starts_with("/") {
+                return Ok(())
+            }
+
+            if self.input[start..].starts_with("//") {
+                self.take_while(|c| c != '\n');
+            } else if self.input[start..].starts_with("/*") {
+                match self.input[start + 2..].find("*/") {
+                    Some(idx) => while self.offset() < start + 2 + idx + 2 {
+                        self.chars.next();
+                    },
+
+                    None => {
+                        while let Some(_) = self.chars.next() {}
+                        return Err(LexError {
+                            kind: LexErrorKind::UnterminatedComment,
+                            span: Span { begin: start, end: start + 2 },
+                        })
+                    }
+                }
+            } else {
+                return Ok(())
+            }
+        }
+    }
+
+    // Picks the two character token if the next character is `next`
+    fn followed_by(&mut self, next: char, long: Tok<'a>, short: Tok<'a>) -> Tok<'a> {
+        if self.peek() == Some(next) {
+            self.chars.next();
+            long
+        } else {
+            short
+        }
+    }
+
+    fn number(&mut self, start: usize) -> Spanned<'a> {
+        let mut end = self.take_while(|c| c.is_digit(10));
+
+        if self.peek() == Some('.') && !self.input[start..].starts_with('.') {
+            self.chars.next();
+            end = self.take_while(|c| c.is_digit(10));
+        }
+
+        if let Some('e') | Some('E') = self.peek() {
+            self.chars.next();
+
+            if let Some('+') | Some('-') = self.peek() {
+                self.chars.next();
+            }
+
+            let digits = self.offset();
+            end = self.take_while(|c| c.is_digit(10));
+
+            if end == digits {
+                return Err(LexError {
+                    kind: LexErrorKind::MissingExponent,
+                    span: Span { begin: start, end: end },
+                })
+            }
+        }
+
+        let text = &self.input[start..end];
+
+        // Shaders use 32-bit floats, so anything larger would become infinite
+        match text.parse::<f64>() {
+            Ok(value) if value <= f32::MAX as f64 => Ok((start, Tok::Number(text), end)),
+            _ => Err(LexError {
+                kind: LexErrorKind::NumberOutOfRange,
+                span: Span { begin: start, end: end },
+            })
+        }
+    }
+
+    fn word(&mut self, start: usize) -> Spanned<'a> {
+        let end = self.take_while(|c| is_name_start(c) || c.is_digit(10));
+
+        let tok = match &self.input[start..end] {
+            "image" => Tok::Image,
+            "return" => Tok::Return,
+            "if" => Tok::If,
+            "else" => Tok::Else,
+            "true" => Tok::True,
+            "false" => Tok::False,
+            "allow" => Tok::Allow,
+            "x" => Tok::X,
+            "y" => Tok::Y,
+            "t" => Tok::T,
+            "mx" => Tok::MouseX,
+            "my" => Tok::MouseY,
+            name => Tok::Name(name),
+        };
+
+        Ok((start, tok, end))
+    }
+
+    // The next token, or the error that stopped one being read
+    fn token(&mut self) -> Option<Spanned<'a>> {
+        if let Err(err) = self.skip() {
+            return Some(Err(err))
+        }
+
+        let (start, c) = match self.chars.next() {
+            Some(next) => next,
+            None => return None,
+        };
+
+        // Floats may leave out the leading zero, as in `.5`
+        let leading_dot = c == '.' && self.peek().map_or(false, |c| c.is_digit(10));
+
+        if c.is_digit(10) || leading_dot {
+            if leading_dot {
+                self.chars.next();
+                self.take_while(|c| c.is_digit(10));
+            }
+
+            return Some(self.number(start))
+        }
+
+        if is_name_start(c) {
+            return Some(self.word(start))
+        }
+
+        let tok = match c {
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            '{' => Tok::LBrace,
+            '}' => Tok::RBrace,
+            ',' => Tok::Comma,
+            ';' => Tok::Semi,
+            '+' => Tok::Plus,
+            '-' => Tok::Minus,
+            '*' => Tok::Star,
+            '/' => Tok::Slash,
+            '%' => Tok::Percent,
+            '^' => Tok::Caret,
+            '<' => self.followed_by('=', Tok::LtEq, Tok::Lt),
+            '>' => self.followed_by('=', Tok::GtEq, Tok::Gt),
+            '=' => self.followed_by('=', Tok::EqEq, Tok::Assign),
+            '!' => self.followed_by('=', Tok::NotEq, Tok::Bang),
+
+            '&' if self.peek() == Some('&') => {
+                self.chars.next();
+                Tok::AndAnd
+            },
+
+            '|' if self.peek() == Some('|') => {
+                self.chars.next();
+                Tok::OrOr
+            },
+
+            c => return Some(Err(LexError {
+                kind: LexErrorKind::UnexpectedCharacter(c),
+                span: Span { begin: start, end: start + c.len_utf8() },
+            })),
+        };
+
+        Some(Ok((start, tok, self.offset())))
+    }
+}
+
+// Names end up in the generated GLSL, so are limited to ASCII
+fn is_name_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Spanned<'a>;
+
+    // Errors become `Tok::Error` tokens, so a script with a stray character
+    // still has the rest of its errors reported
+    fn next(&mut self) -> Option<Spanned<'a>> {
+        self.token().map(|tok| tok.or_else(|err| Ok((err.span.begin, Tok::Error(err.kind), err.span.end))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexer, Tok, LexErrorKind};
+
+    fn toks(src: &str) -> Vec<Tok> {
+        Lexer::new(src).map(|tok| tok.unwrap().1).collect()
+    }
+
+    fn error_kind(tok: Tok) -> Option<LexErrorKind> {
+        match tok {
+            Tok::Error(kind) => Some(kind),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(toks("my_var = .5 * 1.5e-3 + 2E4 + 3. // done\n/* a\n comment */ mx"), vec![
+            Tok::Name("my_var"), Tok::Assign, Tok::Number(".5"), Tok::Star, Tok::Number("1.5e-3"),
+            Tok::Plus, Tok::Number("2E4"), Tok::Plus, Tok::Number("3."), Tok::MouseX,
+        ]);
+
+        assert_eq!(toks("a==b/c"), vec![Tok::Name("a"), Tok::EqEq, Tok::Name("b"), Tok::Slash, Tok::Name("c")]);
+        assert_eq!(toks("!a&&b<=c||d!=e>=f>g"), vec![
+            Tok::Bang, Tok::Name("a"), Tok::AndAnd, Tok::Name("b"), Tok::LtEq, Tok::Name("c"), Tok::OrOr,
+            Tok::Name("d"), Tok::NotEq, Tok::Name("e"), Tok::GtEq, Tok::Name("f"), Tok::Gt, Tok::Name("g"),
+        ]);
+    }
+
+    #[test]
+    fn spans() {
+        let spans: Vec<(usize, usize)> = Lexer::new("  _x1 /* */ 10e2;").map(|tok| {
+            let (begin, _, end) = tok.unwrap();
+            (begin, end)
+        }).collect();
+
+        assert_eq!(spans, vec![(2, 5), (12, 16), (16, 17)]);
+    }
+
+    #[test]
+    fn errors() {
+        let kinds: Vec<LexErrorKind> = toks("a & # 1e+ /* b").into_iter().filter_map(error_kind).collect();
+        assert_eq!(kinds, vec![
+            LexErrorKind::UnexpectedCharacter('&'),
+            LexErrorKind::UnexpectedCharacter('#'),
+            LexErrorKind::MissingExponent,
+            LexErrorKind::UnterminatedComment,
+        ]);
+
+        let kinds: Vec<LexErrorKind> = toks("3.4e38 3.5e38 1e400").into_iter().filter_map(error_kind).collect();
+        assert_eq!(kinds, vec![LexErrorKind::NumberOutOfRange, LexErrorKind::NumberOutOfRange]);
+    }
+}
@@ -1,23 +1,36 @@
 extern crate lalrpop_util;
+extern crate num_cpus;
 
 pub use image::Image;
 
 pub mod ast;
 pub mod span;
 
-pub use analyse::AnalyseError;
+pub use analyse::{AnalyseError, AnalyseErrorKind};
+pub use error::{ParseError, ParseErrorKind, CompileError};
+pub use diagnostic::{Diagnostic, Level};
+pub use lint::{Warning, WarningKind};
+pub use instr::Type;
 pub use image::Uniform;
-
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub struct ParseError<'a>(lalrpop_util::ParseError<usize, (usize, &'a str), ()>);
+pub use eval::{Inputs, Value};
+pub use render::RenderOptions;
+pub use vm::{Program, Machine, BatchMachine, BATCH};
 
 mod analyse;
 mod instr;
 mod grammar;
+mod lexer;
 mod image;
+mod eval;
+mod render;
+mod vm;
+mod pretty;
+mod diagnostic;
+mod error;
+mod lint;
 pub mod functions;
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct Shady {
     items: Vec<instr::Item>
 }
@@ -48,13 +61,93 @@ impl Shady {
     }
 }
 
-pub fn parse_input(input: &str) -> Result<ast::AST, ParseError>{
-    grammar::parse_AST(input).map_err(ParseError)
+pub fn parse_input(input: &str) -> Result<ast::AST, Vec<ParseError>> {
+    match parse_partial(input) {
+        (Some(ast), errors) => if errors.is_empty() { Ok(ast) } else { Err(errors) },
+        (None, errors) => Err(errors),
+    }
+}
+
+// Parses as much of the input as possible, skipping over syntax errors to the
+// end of the statement or block they occur in. The AST leaves out anything
+// that failed to parse, and is missing only if the parser couldn't recover.
+pub fn parse_partial(input: &str) -> (Option<ast::AST>, Vec<ParseError>) {
+    let mut recovered = Vec::new();
+    let tokens = lexer::Lexer::new(input);
+    let result = grammar::parse_AST(&mut recovered, tokens);
+
+    let mut errors: Vec<ParseError> = recovered.into_iter().map(|r| ParseError::new(r.error, input)).collect();
+
+    match result {
+        Ok(ast) => (Some(ast), errors),
+        Err(err) => {
+            errors.push(ParseError::new(err, input));
+            (None, errors)
+        }
+    }
+}
+
+pub fn compile(input: &str) -> Result<Shady, CompileError> {
+    compile_with_warnings(input).map(|(shady, _)| shady)
+}
+
+// Whatever parsed is still analysed when there are syntax errors, so mistakes
+// elsewhere in the script are reported along with them
+pub fn compile_with_warnings(input: &str) -> Result<(Shady, Vec<Warning>), CompileError> {
+    let (ast, parse_errors) = parse_partial(input);
+    let ast = match ast {
+        Some(ast) => ast,
+        None => return Err(CompileError::from_parse(input, parse_errors)),
+    };
+
+    let shady = match ast.analyse() {
+        Ok(shady) => if parse_errors.is_empty() {
+            shady
+        } else {
+            return Err(CompileError::from_parse(input, parse_errors))
+        },
+
+        Err(errors) => return Err(CompileError {
+            parse_errors: parse_errors,
+            ..CompileError::from_analyse(input, errors)
+        }),
+    };
+    let warnings = ast.lint(&shady);
+
+    Ok((shady, warnings))
+}
+
+#[test]
+fn recovery() {
+    let (ast, errors) = parse_partial("image { a = (x, y, 0); b = a + ; c = (1 2, 3); a } image { (x, y, t }");
+    assert_eq!(errors.len(), 3);
+
+    // Only the broken statements and expression are missing
+    let ast = ast.unwrap();
+    assert_eq!(ast.items.len(), 2);
+    assert_eq!(ast.items[0].data.block.data.stmts.len(), 1);
+    assert!(ast.items[0].data.block.data.expr.is_some());
+    assert!(ast.items[1].data.block.data.expr.is_none());
+}
+
+#[test]
+fn lex_error_recovery() {
+    // A stray character is skipped like any other syntax error
+    let src = "image { a = (x, y, 0); b = a # 2; c = (1 2); a }\nimage { (x, z, 0) }";
+    let err = compile(src).unwrap_err();
+
+    let found: Vec<&str> = err.parse_errors.iter().map(|err| &src[err.span.begin..err.span.end]).collect();
+    assert_eq!(found, vec!["#", "2"]);
+    assert_eq!(err.parse_errors[0].kind, ParseErrorKind::UnexpectedCharacter('#'));
+
+    // The parts that did parse are analysed too
+    assert_eq!(err.analyse_errors.len(), 1);
+    assert_eq!(err.analyse_errors[0].to_string(), "61..62: undefined name `z`");
 }
 
 #[test]
 fn test() {
-    let ast = parse_file("../script.shy");
+    let ast = parse_input(include_str!("../../script.shy")).unwrap();
     println!("{:?}", ast);
     let sdy = ast.analyse();
     println!("{:?}", sdy);
@@ -0,0 +1,279 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use ast;
+use span::{Span, Spanned};
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub span: Span,
+    pub file: Option<String>,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum WarningKind {
+    UnusedVariable(String),
+    UnreachableCode,
+    ConstantCondition(bool),
+    FlatImage,
+    // A new variable named after one whose scope has ended
+    Shadowing(String),
+    UnknownLint(String),
+}
+
+impl WarningKind {
+    // The name used to silence the warning with `allow(...)`
+    pub fn lint(&self) -> Option<&'static str> {
+        match *self {
+            WarningKind::UnusedVariable(_) => Some("unused"),
+            WarningKind::UnreachableCode => Some("unreachable"),
+            WarningKind::ConstantCondition(_) => Some("constant"),
+            WarningKind::FlatImage => Some("flat"),
+            WarningKind::Shadowing(_) => Some("shadow"),
+            WarningKind::UnknownLint(_) => None,
+        }
+    }
+}
+
+pub static LINTS: &'static [&'static str] = &["unused", "unreachable", "constant", "flat", "shadow"];
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        ::error::write_located(f, &self.file, self.span, &self.diagnostic().message)
+    }
+}
+
+// Lints are checked on the AST, which keeps the spans the IR drops, using the
+// analysed items for anything that needs types or inputs.
+impl ast::AST {
+    pub fn lint(&self, shady: &::Shady) -> Vec<Warning> {
+        let mut linter = Linter::new();
+
+        for (idx, item) in self.items.iter().enumerate() {
+            linter.item(item, shady.get(idx));
+        }
+
+        let mut warnings = linter.warnings;
+        let allowed: HashSet<&str> = self.allow.iter().map(|name| &name.data[..]).collect();
+        warnings.retain(|warning| warning.kind.lint().map_or(true, |lint| !allowed.contains(lint)));
+
+        for name in &self.allow {
+            if !LINTS.contains(&&name.data[..]) {
+                warnings.push(warning(name.span, WarningKind::UnknownLint(name.data.clone())));
+            }
+        }
+
+        warnings.sort_by_key(|warning| warning.span.begin);
+        warnings
+    }
+}
+
+fn warning(span: Span, kind: WarningKind) -> Warning {
+    Warning {
+        kind: kind,
+        span: span,
+        file: None,
+    }
+}
+
+struct Linter {
+    warnings: Vec<Warning>,
+    // The first assignment to each variable, and the variables read so far
+    decls: Vec<(String, Span)>,
+    reads: HashSet<String>,
+    // The names in scope in the generated shader, and those whose scope has
+    // ended
+    scopes: Vec<HashSet<String>>,
+    expired: HashSet<String>,
+}
+
+impl Linter {
+    fn new() -> Linter {
+        Linter {
+            warnings: Vec::new(),
+            decls: Vec::new(),
+            reads: HashSet::new(),
+            scopes: vec![HashSet::new()],
+            expired: HashSet::new(),
+        }
+    }
+
+    fn item(&mut self, item: &Spanned<ast::Item>, analysed: &::instr::Item) {
+        self.decls.clear();
+        self.reads.clear();
+        self.scopes = vec![HashSet::new()];
+        self.expired.clear();
+
+        self.block(&item.data.block);
+
+        for &(ref name, span) in &self.decls {
+            if !self.reads.contains(name) {
+                self.warnings.push(warning(span, WarningKind::UnusedVariable(name.clone())));
+            }
+        }
+
+        if !analysed.vars.contains(&ast::KeyVar::XPos) && !analysed.vars.contains(&ast::KeyVar::YPos) {
+            let span = Span { begin: item.span.begin, end: item.span.begin + "image".len() };
+            self.warnings.push(warning(span, WarningKind::FlatImage));
+        }
+    }
+
+    fn block(&mut self, block: &Spanned<ast::Block>) {
+        let mut returned = false;
+
+        for (idx, stmt) in block.data.stmts.iter().enumerate() {
+            match stmt.data {
+                ast::Stmt::Assignment(ref name, ref expr) => {
+                    self.expr(expr);
+                    self.assign(name, stmt.span);
+
+                    if !self.decls.iter().any(|&(ref decl, _)| decl == name) {
+                        self.decls.push((name.clone(), stmt.span));
+                    }
+                },
+
+                ast::Stmt::Return(ref expr) => {
+                    self.expr(expr);
+
+                    if !returned {
+                        self.unreachable(&block.data.stmts[idx + 1..], &block.data.expr);
+                        returned = true;
+                    }
+                },
+
+                ast::Stmt::Expr(ref stmt) => self.expr_stmt(stmt),
+            }
+        }
+
+        if let Some(ref expr) = block.data.expr {
+            self.expr(expr);
+        }
+    }
+
+    // The names first assigned in a branch
+    fn branch(&mut self, block: &Spanned<ast::Block>) -> HashSet<String> {
+        self.scopes.push(HashSet::new());
+        self.block(block);
+        self.scopes.pop().unwrap()
+    }
+
+    fn assign(&mut self, name: &str, span: Span) {
+        if self.scopes.iter().any(|scope| scope.contains(name)) {
+            return
+        }
+
+        if self.expired.contains(name) {
+            self.warnings.push(warning(span, WarningKind::Shadowing(name.to_owned())));
+        }
+
+        self.scopes.last_mut().unwrap().insert(name.to_owned());
+    }
+
+    fn unreachable(&mut self, stmts: &[Spanned<ast::Stmt>], expr: &Option<Spanned<ast::Expr>>) {
+        let begin = stmts.first().map(|stmt| stmt.span).or(expr.as_ref().map(|expr| expr.span));
+        let end = expr.as_ref().map(|expr| expr.span).or(stmts.last().map(|stmt| stmt.span));
+
+        if let (Some(begin), Some(end)) = (begin, end) {
+            let span = Span { begin: begin.begin, end: end.end };
+            self.warnings.push(warning(span, WarningKind::UnreachableCode));
+        }
+    }
+
+    fn expr_stmt(&mut self, stmt: &ast::ExprStmt) {
+        match *stmt {
+            ast::ExprStmt::ITE(ref ite) => {
+                if let ast::Expr::Bool(b) = ite.0.data {
+                    self.warnings.push(warning(ite.0.span, WarningKind::ConstantCondition(b)));
+                }
+
+                self.expr(&ite.0);
+                let t = self.branch(&ite.1);
+                let e = ite.2.as_ref().map_or(HashSet::new(), |e| self.branch(e));
+
+                // Names first assigned in both branches outlive the `if`
+                for name in t.intersection(&e) {
+                    self.scopes.last_mut().unwrap().insert(name.clone());
+                }
+
+                self.expired.extend(t.symmetric_difference(&e).cloned());
+            },
+        }
+    }
+
+    fn expr(&mut self, expr: &Spanned<ast::Expr>) {
+        match expr.data {
+            ast::Expr::KeyVar(_) | ast::Expr::Literal(_) | ast::Expr::Bool(_) => (),
+
+            ast::Expr::Var(ref name) => {
+                self.reads.insert(name.clone());
+            },
+
+            ast::Expr::App(_, ref exprs) => for expr in exprs {
+                self.expr(expr);
+            },
+
+            ast::Expr::Vec2(ref exprs) => {
+                self.expr(&exprs.0);
+                self.expr(&exprs.1);
+            },
+
+            ast::Expr::Vec3(ref exprs) => {
+                self.expr(&exprs.0);
+                self.expr(&exprs.1);
+                self.expr(&exprs.2);
+            },
+
+            ast::Expr::BinOp(_, ref exprs) => {
+                self.expr(&exprs.0);
+                self.expr(&exprs.1);
+            },
+
+            ast::Expr::UnOp(_, ref expr) => self.expr(expr),
+
+            ast::Expr::Stmt(ref stmt) => self.expr_stmt(stmt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WarningKind;
+
+    fn warnings(src: &str) -> Vec<WarningKind> {
+        let ast = ::parse_input(src).unwrap();
+        let sdy = ast.analyse().unwrap();
+        ast.lint(&sdy).into_iter().map(|warning| warning.kind).collect()
+    }
+
+    #[test]
+    fn lints() {
+        assert_eq!(warnings("image { a = 1; b = x; c = (b, y, 0); if true { return c; }; c }"), vec![
+            WarningKind::UnusedVariable("a".to_owned()),
+            WarningKind::ConstantCondition(true),
+        ]);
+
+        assert_eq!(warnings("image { c = (t, 0, 0); return c; c = c * 2; c }"), vec![
+            WarningKind::FlatImage,
+            WarningKind::UnreachableCode,
+        ]);
+
+        assert!(warnings("image { (x, y, 0) }").is_empty());
+    }
+
+    #[test]
+    fn shadowing() {
+        assert_eq!(warnings("image { if x < y { a = 1; b = x; } else { b = y; }; if y < 0.5 { a = 2; return (a, b, 0); }; (b, b, b) }"), vec![
+            WarningKind::Shadowing("a".to_owned()),
+        ]);
+
+        // Updating an outer variable, or one assigned in both branches, is fine
+        assert!(warnings("image { c = 0; if x < y { c = 1; d = 2; } else { d = 3; }; if d > 2 { c = 2; d = 4; }; (c, d, y) }").is_empty());
+    }
+
+    #[test]
+    fn allow() {
+        assert_eq!(warnings("allow(unused, flat); image { a = 1; (0, 0, 0) }"), vec![]);
+        assert_eq!(warnings("allow(unsued); image { (x, y, 0) }"), vec![WarningKind::UnknownLint("unsued".to_owned())]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt;
+
+use ::{ast, instr};
+use span::Spanned;
+
+// Pretty printers for inspecting the pipeline. Binary operators are always
+// parenthesised so the printed tree shows exactly how the script was parsed.
+
+impl fmt::Display for ast::AST {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !self.allow.is_empty() {
+            let names: Vec<&str> = self.allow.iter().map(|name| &name.data[..]).collect();
+            try!(writeln!(f, "allow({});", names.join(", ")));
+        }
+
+        for item in &self.items {
+            match item.data.item {
+                ast::ItemKind::Image => try!(write!(f, "image ")),
+            }
+
+            try!(write_ast_block(f, &item.data.block, 0));
+            try!(writeln!(f, ""));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for ::Shady {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for item in &self.items {
+            let vars: Vec<String> = item.vars.iter().map(|&var| keyvar(var).to_owned()).collect();
+
+            match item.kind {
+                ast::ItemKind::Image => try!(write!(f, "image")),
+            }
+
+            try!(writeln!(f, "({}) -> {} {{", vars.join(", "), item.ret));
+            try!(write_ir_instrs(f, &item.instrs, 1));
+            try!(writeln!(f, "}}"));
+        }
+
+        Ok(())
+    }
+}
+
+fn indent(f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+    for _ in 0..depth {
+        try!(write!(f, "    "));
+    }
+
+    Ok(())
+}
+
+fn keyvar(var: ast::KeyVar) -> &'static str {
+    match var {
+        ast::KeyVar::XPos => "x",
+        ast::KeyVar::YPos => "y",
+        ast::KeyVar::Time => "t",
+        ast::KeyVar::MouseX => "mx",
+        ast::KeyVar::MouseY => "my",
+    }
+}
+
+pub(crate) fn op(op: ast::OpKind) -> &'static str {
+    match op {
+        ast::OpKind::ArithOp(ast::ArithOpKind::Add) => "+",
+        ast::OpKind::ArithOp(ast::ArithOpKind::Sub) => "-",
+        ast::OpKind::ArithOp(ast::ArithOpKind::Mul) => "*",
+        ast::OpKind::ArithOp(ast::ArithOpKind::Div) => "/",
+        ast::OpKind::ArithOp(ast::ArithOpKind::Mod) => "%",
+        ast::OpKind::ArithOp(ast::ArithOpKind::Pow) => "^",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Lt) => "<",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Gt) => ">",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Eq) => "==",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Le) => "<=",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Ge) => ">=",
+        ast::OpKind::CmpOp(ast::CmpOpKind::Ne) => "!=",
+        ast::OpKind::LogicOp(ast::LogicOpKind::And) => "&&",
+        ast::OpKind::LogicOp(ast::LogicOpKind::Or) => "||",
+    }
+}
+
+pub(crate) fn unop(op: ast::UnOpKind) -> &'static str {
+    match op {
+        ast::UnOpKind::Neg => "-",
+        ast::UnOpKind::Not => "!",
+    }
+}
+
+fn write_ast_block(f: &mut fmt::Formatter, block: &Spanned<ast::Block>, depth: usize) -> fmt::Result {
+    try!(writeln!(f, "{{"));
+
+    for stmt in &block.data.stmts {
+        try!(indent(f, depth + 1));
+
+        match stmt.data {
+            ast::Stmt::Assignment(ref name, ref expr) => {
+                try!(write!(f, "{} = ", name));
+                try!(write_ast_expr(f, &expr.data, depth + 1));
+            },
+
+            ast::Stmt::Return(ref expr) => {
+                try!(write!(f, "return "));
+                try!(write_ast_expr(f, &expr.data, depth + 1));
+            },
+
+            ast::Stmt::Expr(ref stmt) => try!(write_ast_expr_stmt(f, stmt, depth + 1)),
+        }
+
+        try!(writeln!(f, ";"));
+    }
+
+    if let Some(ref expr) = block.data.expr {
+        try!(indent(f, depth + 1));
+        try!(write_ast_expr(f, &expr.data, depth + 1));
+        try!(writeln!(f, ""));
+    }
+
+    try!(indent(f, depth));
+    write!(f, "}}")
+}
+
+fn write_ast_expr_stmt(f: &mut fmt::Formatter, stmt: &ast::ExprStmt, depth: usize) -> fmt::Result {
+    match *stmt {
+        ast::ExprStmt::ITE(ref ite) => {
+            try!(write!(f, "if "));
+            try!(write_ast_expr(f, &ite.0.data, depth));
+            try!(write!(f, " "));
+            try!(write_ast_block(f, &ite.1, depth));
+
+            if let Some(ref e) = ite.2 {
+                try!(write!(f, " else "));
+                try!(write_ast_block(f, e, depth));
+            }
+
+            Ok(())
+        },
+    }
+}
+
+fn write_ast_exprs(f: &mut fmt::Formatter, exprs: &[&Spanned<ast::Expr>], depth: usize) -> fmt::Result {
+    for (idx, expr) in exprs.iter().enumerate() {
+        if idx > 0 {
+            try!(write!(f, ", "));
+        }
+
+        try!(write_ast_expr(f, &expr.data, depth));
+    }
+
+    Ok(())
+}
+
+fn write_ast_expr(f: &mut fmt::Formatter, expr: &ast::Expr, depth: usize) -> fmt::Result {
+    match *expr {
+        ast::Expr::KeyVar(var) => write!(f, "{}", keyvar(var)),
+        ast::Expr::Literal(lit) => write!(f, "{}", lit),
+        ast::Expr::Bool(b) => write!(f, "{}", b),
+        ast::Expr::Var(ref name) => write!(f, "{}", name),
+
+        ast::Expr::App(ref name, ref exprs) => {
+            try!(write!(f, "{}(", name));
+            try!(write_ast_exprs(f, &exprs.iter().collect::<Vec<_>>(), depth));
+            write!(f, ")")
+        },
+
+        ast::Expr::Vec2(ref exprs) => {
+            try!(write!(f, "("));
+            try!(write_ast_exprs(f, &[&exprs.0, &exprs.1], depth));
+            write!(f, ")")
+        },
+
+        ast::Expr::Vec3(ref exprs) => {
+            try!(write!(f, "("));
+            try!(write_ast_exprs(f, &[&exprs.0, &exprs.1, &exprs.2], depth));
+            write!(f, ")")
+        },
+
+        ast::Expr::BinOp(kind, ref exprs) => {
+            try!(write!(f, "("));
+            try!(write_ast_expr(f, &exprs.0.data, depth));
+            try!(write!(f, " {} ", op(kind)));
+            try!(write_ast_expr(f, &exprs.1.data, depth));
+            write!(f, ")")
+        },
+
+        ast::Expr::UnOp(kind, ref expr) => {
+            try!(write!(f, "{}", unop(kind)));
+            write_ast_expr(f, &expr.data, depth)
+        },
+
+        ast::Expr::Stmt(ref stmt) => write_ast_expr_stmt(f, stmt, depth),
+    }
+}
+
+fn write_ir_instrs(f: &mut fmt::Formatter, instrs: &[instr::Instr], depth: usize) -> fmt::Result {
+    for instr in instrs {
+        try!(indent(f, depth));
+
+        match *instr {
+            instr::Instr::Decl(ref name, ty, ref expr) => {
+                try!(write!(f, "decl {} {}", ty, name));
+
+                if let Some(ref expr) = *expr {
+                    try!(write!(f, " = "));
+                    try!(write_ir_expr(f, expr));
+                }
+            },
+
+            instr::Instr::Assignment(ref name, ref expr) => {
+                try!(write!(f, "{} = ", name));
+                try!(write_ir_expr(f, &expr.expr));
+                try!(write!(f, " : {}", expr.ty));
+            },
+
+            instr::Instr::Return(ref expr) => {
+                try!(write!(f, "return "));
+                try!(write_ir_expr(f, &expr.expr));
+                try!(write!(f, " : {}", expr.ty));
+            },
+
+            instr::Instr::ITE(ref cond, ref t, ref e) => {
+                try!(write!(f, "if "));
+                try!(write_ir_expr(f, cond));
+                try!(write_ir_block(f, t, depth));
+
+                if let Some(ref e) = *e {
+                    try!(write!(f, " else"));
+                    try!(write_ir_block(f, e, depth));
+                }
+            },
+        }
+
+        try!(writeln!(f, ""));
+    }
+
+    Ok(())
+}
+
+fn write_ir_block(f: &mut fmt::Formatter, block: &instr::Block, depth: usize) -> fmt::Result {
+    match block.ret {
+        Some(ty) => try!(writeln!(f, " -> {} {{", ty)),
+        None => try!(writeln!(f, " {{")),
+    }
+
+    try!(write_ir_instrs(f, &block.instrs, depth + 1));
+    try!(indent(f, depth));
+    write!(f, "}}")
+}
+
+fn write_ir_exprs(f: &mut fmt::Formatter, exprs: &[&instr::ExprKind]) -> fmt::Result {
+    for (idx, expr) in exprs.iter().enumerate() {
+        if idx > 0 {
+            try!(write!(f, ", "));
+        }
+
+        try!(write_ir_expr(f, expr));
+    }
+
+    Ok(())
+}
+
+fn write_ir_expr(f: &mut fmt::Formatter, expr: &instr::ExprKind) -> fmt::Result {
+    match *expr {
+        instr::ExprKind::KeyVar(var) => write!(f, "{}", keyvar(var)),
+        instr::ExprKind::Literal(lit) => write!(f, "{}", lit),
+        instr::ExprKind::Bool(b) => write!(f, "{}", b),
+        instr::ExprKind::Var(ref name) => write!(f, "{}", name),
+
+        instr::ExprKind::Application(ref name, ref exprs) => {
+            try!(write!(f, "{}(", name));
+            try!(write_ir_exprs(f, &exprs.iter().collect::<Vec<_>>()));
+            write!(f, ")")
+        },
+
+        instr::ExprKind::Vec2(ref exprs) => {
+            try!(write!(f, "vec2("));
+            try!(write_ir_exprs(f, &[&exprs.0, &exprs.1]));
+            write!(f, ")")
+        },
+
+        instr::ExprKind::Vec3(ref exprs) => {
+            try!(write!(f, "vec3("));
+            try!(write_ir_exprs(f, &[&exprs.0, &exprs.1, &exprs.2]));
+            write!(f, ")")
+        },
+
+        instr::ExprKind::BinOp(kind, ref exprs) => {
+            try!(write!(f, "("));
+            try!(write_ir_expr(f, &exprs.0));
+            try!(write!(f, " {} ", op(kind)));
+            try!(write_ir_expr(f, &exprs.1));
+            write!(f, ")")
+        },
+
+        instr::ExprKind::UnOp(kind, ref expr) => {
+            try!(write!(f, "{}", unop(kind)));
+            write_ir_expr(f, expr)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn stages() {
+        let src = "image { c = (x, y * 2 + 1, 0.5); if x < mx { return c; }; c }";
+        let ast = ::parse_input(src).unwrap();
+
+        assert_eq!(format!("{}", ast), "image {\n    c = (x, ((y * 2) + 1), 0.5);\n    if (x < mx) {\n        return c;\n    };\n    c\n}\n");
+        assert_eq!(format!("{}", ast.analyse().unwrap()), "image(x, y, mx) -> vec3 {\n    decl vec3 c = vec3(x, ((y * 2) + 1), 0.5)\n    if (x < mx) -> vec3 {\n        return c : vec3\n    }\n    return c : vec3\n}\n");
+    }
+}
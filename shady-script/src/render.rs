@@ -0,0 +1,222 @@
+use std::cmp;
+use std::panic;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use num_cpus;
+
+use image::Image;
+use eval::{Inputs, Value};
+use vm::{Program, BATCH};
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RenderOptions {
+    pub threads: usize,
+    pub tile_size: usize,
+}
+
+impl Default for RenderOptions {
+    fn default() -> RenderOptions {
+        RenderOptions {
+            threads: num_cpus::get(),
+            tile_size: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    width: usize,
+    height: usize,
+    t: f32,
+    mx: f32,
+    my: f32,
+}
+
+impl<'a> Image<'a> {
+    pub fn render(&self, width: usize, height: usize, t: f32, mx: f32, my: f32) -> Vec<u8> {
+        self.render_with(&RenderOptions::default(), width, height, t, mx, my)
+    }
+
+    // Renders the image into a row-major, top-down RGB8 buffer. Pixels are
+    // sampled at their centres with y increasing upwards, matching the uv
+    // coordinates the viewer's fragment shader receives.
+    //
+    // The target is split into square tiles which are handed out to worker
+    // threads; every pixel is evaluated independently so the result does not
+    // depend on the thread count or tile size.
+    pub fn render_with(&self, options: &RenderOptions, width: usize, height: usize, t: f32, mx: f32, my: f32) -> Vec<u8> {
+        let frame = Frame {
+            width: width,
+            height: height,
+            t: t,
+            mx: mx,
+            my: my,
+        };
+
+        let tile_size = cmp::max(options.tile_size, 1);
+        let mut tiles = Vec::new();
+        for y in (0..height).filter(|y| y % tile_size == 0) {
+            for x in (0..width).filter(|x| x % tile_size == 0) {
+                tiles.push(Tile {
+                    x: x,
+                    y: y,
+                    width: cmp::min(tile_size, width - x),
+                    height: cmp::min(tile_size, height - y),
+                });
+            }
+        }
+
+        let mut buffer = vec![0; width * height * 3];
+        let threads = cmp::min(cmp::max(options.threads, 1), tiles.len());
+
+        let program = self.program();
+
+        if threads <= 1 {
+            for tile in &tiles {
+                blit(&mut buffer, width, tile, &render_tile(&program, tile, &frame));
+            }
+
+            return buffer
+        }
+
+        let program = Arc::new(program);
+        let tiles = Arc::new(tiles);
+        let next = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = mpsc::channel();
+
+        let workers: Vec<_> = (0..threads).map(|_| {
+            let (program, tiles, next, tx) = (program.clone(), tiles.clone(), next.clone(), tx.clone());
+
+            thread::spawn(move || loop {
+                let idx = next.fetch_add(1, Ordering::SeqCst);
+                match tiles.get(idx) {
+                    Some(tile) => if tx.send((idx, render_tile(&program, tile, &frame))).is_err() {
+                        return
+                    },
+
+                    None => return
+                }
+            })
+        }).collect();
+
+        drop(tx);
+        for (idx, data) in rx {
+            blit(&mut buffer, width, &tiles[idx], &data);
+        }
+
+        // A worker that panicked has left its tiles blank, so fail the same
+        // way rendering on this thread would
+        for worker in workers {
+            if let Err(err) = worker.join() {
+                panic::resume_unwind(err);
+            }
+        }
+
+        buffer
+    }
+}
+
+fn render_tile(program: &Program, tile: &Tile, frame: &Frame) -> Vec<u8> {
+    let mut data = Vec::with_capacity(tile.width * tile.height * 3);
+    let mut machine = program.batch_machine();
+    let mut inputs = Vec::with_capacity(BATCH);
+    let mut out = [Value::Void; BATCH];
+
+    for row in tile.y..tile.y + tile.height {
+        for begin in (tile.x..tile.x + tile.width).filter(|col| (col - tile.x) % BATCH == 0) {
+            let end = cmp::min(begin + BATCH, tile.x + tile.width);
+
+            inputs.clear();
+            inputs.extend((begin..end).map(|col| Inputs {
+                x: (col as f32 + 0.5) / frame.width as f32,
+                y: 1.0 - (row as f32 + 0.5) / frame.height as f32,
+                t: frame.t,
+                mx: frame.mx,
+                my: frame.my,
+            }));
+
+            machine.eval(&inputs, &mut out);
+
+            for value in &out[..inputs.len()] {
+                let colour: [f32; 3] = value.get();
+                data.extend(colour.iter().map(|&c| to_u8(c)));
+            }
+        }
+    }
+
+    data
+}
+
+fn blit(buffer: &mut [u8], width: usize, tile: &Tile, data: &[u8]) {
+    let stride = tile.width * 3;
+
+    for (row, src) in data.chunks(stride).enumerate() {
+        let begin = ((tile.y + row) * width + tile.x) * 3;
+        buffer[begin..begin + stride].copy_from_slice(src);
+    }
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c.max(0.0).min(1.0) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use ::{ast, instr};
+    use super::RenderOptions;
+
+    #[test]
+    fn render_orientation() {
+        let sdy = ::parse_input("image { (x, y, 0.5) }").unwrap().analyse().unwrap();
+        sdy.with_images(|image| {
+            let buffer = image.render(2, 2, 0.0, 0.0, 0.0);
+            assert_eq!(buffer, vec![
+                64, 191, 128,   191, 191, 128,
+                64, 64, 128,    191, 64, 128,
+            ]);
+        });
+    }
+
+    #[test]
+    fn render_deterministic() {
+        let sdy = ::parse_input("image { if x < y { return (x, t, 0); }; (sin(x * 10), y, mx) }").unwrap().analyse().unwrap();
+        sdy.with_images(|image| {
+            let expected = image.render_with(&RenderOptions { threads: 1, tile_size: 1000 }, 37, 23, 1.5, 0.25, 0.0);
+
+            for &(threads, tile_size) in &[(1, 1), (2, 5), (4, 8), (16, 64), (3, 0)] {
+                let options = RenderOptions { threads: threads, tile_size: tile_size };
+                assert_eq!(image.render_with(&options, 37, 23, 1.5, 0.25, 0.0), expected);
+            }
+        });
+    }
+
+    #[test]
+    #[should_panic]
+    fn worker_panics() {
+        // Analysis never lets an image return a float, so every tile panics
+        let sdy = ::Shady {
+            items: vec![instr::Item {
+                ret: instr::Type::Float,
+                kind: ast::ItemKind::Image,
+                instrs: vec![instr::Instr::Return(instr::Expr { ty: instr::Type::Float, expr: instr::ExprKind::Literal(1.0) })],
+                vars: BTreeSet::new(),
+            }]
+        };
+
+        sdy.with_images(|image| {
+            image.render_with(&RenderOptions { threads: 4, tile_size: 2 }, 8, 8, 0.0, 0.0, 0.0);
+        });
+    }
+}
@@ -7,12 +7,12 @@ pub struct Span {
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
-pub struct Spanned<T: Debug + Eq + PartialEq + Clone> {
+pub struct Spanned<T: Debug + PartialEq + Clone> {
     pub span: Span,
     pub data: T
 }
 
-pub fn spanned<T: Debug + Eq + PartialEq + Clone>(begin: usize, end: usize, data: T) -> Spanned<T> {
+pub fn spanned<T: Debug + PartialEq + Clone>(begin: usize, end: usize, data: T) -> Spanned<T> {
     Spanned { 
         span: Span {
             begin: begin,
@@ -0,0 +1,303 @@
+use ast;
+use eval::{self, Inputs, Value};
+use super::{Op, Program, Lanes, to_value, from_value};
+
+// The number of pixels evaluated together by a BatchMachine
+pub const BATCH: usize = 8;
+
+// Registers are stored component-major, so each arithmetic op is a loop over
+// BATCH contiguous floats per component.
+type Wide = [[f32; BATCH]; 3];
+type Mask = [bool; BATCH];
+
+// Runs a Program over a packet of pixels at once, the way a GPU runs the
+// generated shader: every op executes for the whole packet, and branches
+// only commit writes in the lanes whose condition selected them. A branch
+// that no lane takes is skipped entirely.
+pub struct BatchMachine<'a> {
+    program: &'a Program,
+    regs: Vec<Wide>,
+    branches: Vec<(Mask, Mask)>,
+}
+
+impl Program {
+    pub fn batch_machine(&self) -> BatchMachine {
+        BatchMachine {
+            program: self,
+            regs: self.init.iter().map(|&lanes| splat(lanes)).collect(),
+            branches: Vec::new(),
+        }
+    }
+}
+
+impl<'a> BatchMachine<'a> {
+    // Evaluates up to BATCH pixels, writing each result to the matching
+    // element of `out`.
+    pub fn eval(&mut self, inputs: &[Inputs], out: &mut [Value]) {
+        assert!(inputs.len() <= BATCH, "at most {} pixels can be evaluated at once", BATCH);
+        assert!(out.len() >= inputs.len());
+
+        let regs = &mut self.regs;
+        let branches = &mut self.branches;
+        let code = &self.program.code;
+        let ret = self.program.ret;
+
+        let mut alive = [false; BATCH];
+        for (i, inputs) in inputs.iter().enumerate() {
+            alive[i] = true;
+            out[i] = Value::zero(ret);
+
+            let keyvars = [inputs.x, inputs.y, inputs.t, inputs.mx, inputs.my];
+            for (reg, &value) in keyvars.iter().enumerate() {
+                for c in 0..3 {
+                    regs[reg][c][i] = value;
+                }
+            }
+        }
+
+        let mut mask = alive;
+        branches.clear();
+
+        let mut pc = 0;
+        while pc < code.len() {
+            match code[pc] {
+                Op::Arith(op, d, a, b) => {
+                    let (a, b) = (regs[a], regs[b]);
+                    let mut r = [[0.0; BATCH]; 3];
+
+                    for c in 0..3 {
+                        for i in 0..BATCH {
+                            r[c][i] = match op {
+                                ast::ArithOpKind::Add => a[c][i] + b[c][i],
+                                ast::ArithOpKind::Sub => a[c][i] - b[c][i],
+                                ast::ArithOpKind::Mul => a[c][i] * b[c][i],
+                                ast::ArithOpKind::Div => a[c][i] / b[c][i],
+                                ast::ArithOpKind::Mod => eval::modulo(a[c][i], b[c][i]),
+                                ast::ArithOpKind::Pow => a[c][i].powf(b[c][i]),
+                            };
+                        }
+                    }
+
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Neg(d, a) => {
+                    let a = regs[a];
+                    let mut r = [[0.0; BATCH]; 3];
+
+                    for c in 0..3 {
+                        for i in 0..BATCH {
+                            r[c][i] = -a[c][i];
+                        }
+                    }
+
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Cmp(op, n, d, a, b) => {
+                    let (a, b) = (regs[a], regs[b]);
+                    let mut r = [true; BATCH];
+
+                    // Vectors are unequal if any component is, so `!=` is
+                    // worked out as the inverse of `==`
+                    let (op, invert) = match op {
+                        ast::CmpOpKind::Ne => (ast::CmpOpKind::Eq, true),
+                        op => (op, false),
+                    };
+
+                    for c in 0..n {
+                        for i in 0..BATCH {
+                            r[i] &= eval::compare(op, a[c][i], b[c][i]);
+                        }
+                    }
+
+                    let mut wide = [[0.0; BATCH]; 3];
+                    for c in 0..3 {
+                        for i in 0..BATCH {
+                            wide[c][i] = if r[i] != invert { 1.0 } else { 0.0 };
+                        }
+                    }
+
+                    blend(&mut regs[d], &wide, &mask);
+                },
+
+                Op::Not(d, a) => {
+                    let a = regs[a];
+                    let mut r = [[0.0; BATCH]; 3];
+
+                    for c in 0..3 {
+                        for i in 0..BATCH {
+                            r[c][i] = if a[0][i] == 0.0 { 1.0 } else { 0.0 };
+                        }
+                    }
+
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Call(f, d, ref args) => {
+                    let mut values = Vec::with_capacity(args.len());
+
+                    for i in (0..BATCH).filter(|&i| mask[i]) {
+                        values.clear();
+                        values.extend(args.iter().map(|&(r, ty)| to_value(lane(&regs[r], i), ty)));
+                        set_lane(&mut regs[d], i, from_value(f(&values)));
+                    }
+                },
+
+                Op::Vec2(d, a, b) => {
+                    let r = [regs[a][0], regs[b][0], [0.0; BATCH]];
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Vec3(d, a, b, c) => {
+                    let r = [regs[a][0], regs[b][0], regs[c][0]];
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Move(d, s) => {
+                    let r = regs[s];
+                    blend(&mut regs[d], &r, &mask);
+                },
+
+                Op::Zero(d) => blend(&mut regs[d], &[[0.0; BATCH]; 3], &mask),
+
+                Op::If(c, target) => {
+                    let mut cond = [false; BATCH];
+                    for i in 0..BATCH {
+                        cond[i] = regs[c][0][i] != 0.0;
+                    }
+
+                    branches.push((mask, cond));
+                    mask = and(&mask, &cond);
+
+                    if !any(&mask) {
+                        pc = target;
+                        continue
+                    }
+                },
+
+                Op::Else(target) => {
+                    let (outer, cond) = *branches.last().unwrap();
+                    mask = and(&and(&outer, &alive), &not(&cond));
+
+                    if !any(&mask) {
+                        pc = target;
+                        continue
+                    }
+                },
+
+                Op::EndIf => {
+                    let (outer, _) = branches.pop().unwrap();
+                    mask = and(&outer, &alive);
+                },
+
+                Op::Return(r) => {
+                    for i in (0..BATCH).filter(|&i| mask[i]) {
+                        out[i] = to_value(lane(&regs[r], i), ret);
+                    }
+
+                    alive = and(&alive, &not(&mask));
+                    mask = [false; BATCH];
+
+                    if !any(&alive) {
+                        return
+                    }
+                },
+            }
+
+            pc += 1;
+        }
+    }
+}
+
+fn splat(lanes: Lanes) -> Wide {
+    [[lanes[0]; BATCH], [lanes[1]; BATCH], [lanes[2]; BATCH]]
+}
+
+fn lane(wide: &Wide, i: usize) -> Lanes {
+    [wide[0][i], wide[1][i], wide[2][i]]
+}
+
+fn set_lane(wide: &mut Wide, i: usize, lanes: Lanes) {
+    for c in 0..3 {
+        wide[c][i] = lanes[c];
+    }
+}
+
+fn blend(dst: &mut Wide, src: &Wide, mask: &Mask) {
+    for c in 0..3 {
+        for i in 0..BATCH {
+            if mask[i] {
+                dst[c][i] = src[c][i];
+            }
+        }
+    }
+}
+
+fn and(a: &Mask, b: &Mask) -> Mask {
+    let mut r = [false; BATCH];
+    for i in 0..BATCH {
+        r[i] = a[i] && b[i];
+    }
+
+    r
+}
+
+fn not(a: &Mask) -> Mask {
+    let mut r = [false; BATCH];
+    for i in 0..BATCH {
+        r[i] = !a[i];
+    }
+
+    r
+}
+
+fn any(a: &Mask) -> bool {
+    a.iter().any(|&b| b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BATCH;
+    use eval::{Inputs, Value};
+    use vm::Program;
+
+    fn check(src: &str) {
+        let sdy = ::parse_input(src).unwrap().analyse().unwrap();
+        let program = Program::compile(sdy.get(0));
+        let mut machine = program.machine();
+        let mut batch = program.batch_machine();
+
+        let inputs: Vec<Inputs> = (0..121).map(|i| Inputs {
+            x: (i % 11) as f32 / 10.0,
+            y: (i / 11) as f32 / 10.0,
+            t: 1.25,
+            mx: 0.3,
+            my: 0.6
+        }).collect();
+
+        // 121 is not a multiple of BATCH, so the last packet is partial
+        for packet in inputs.chunks(BATCH) {
+            let mut out = [Value::Void; BATCH];
+            batch.eval(packet, &mut out);
+
+            for (inputs, value) in packet.iter().zip(out.iter()) {
+                assert_eq!(*value, machine.eval(inputs), "{} at {:?}", src, inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_scalar() {
+        check("image { (x, y, t) }");
+        check("image { a = sin(x * 10) + cos(y); b = max(a, mx); (a, b, min(t, my)) }");
+        check("image { c = (0, 0, 0); if x < 0.5 { c = (1, y, 0); } else { return (0, 0, x); }; c }");
+        check("image { if x == y { return (1, 1, 1); }; if x > mx && y > my { return (x, 0, 0); }; (0, y, 0) }");
+        check("image { c = (x, y, 0); if x < 0.5 { if y < 0.5 { c = c * 2; } else { return (1, 0, 0); }; c = c + 0.1; } else { if y > 0.9 { return c; }; }; c }");
+        check("image { if x < 0.5 { return (x, x, x); } else { return (y, y, y); }; }");
+        check("image { a = -(x - 0.5) % 0.3; v = -((x, y, t) ^ (y, 2, x)); (a, 2 ^ -y, -a) + v % 0.7 }");
+        check("image { a = x <= 0.5 && (y >= 0.3 || !(x != y)); if a || (x, y) != (0.5, 0.5) && y < 0.2 { return (1, 0, 0); }; (a, x >= y, false) }");
+        check("image { c = if x < 0.5 { (1, 0, 0) } else { d = y * 2; (0, d, 1) }; a = (if y > 0.5 { x } else { if x > 0.3 { return c; } else { 0.5 } }) + 1; c * a }");
+        check("image { if x > 0.5 && (if y < 0.5 { return (1, 0, 0); } else { true }) { return (0, 1, 0); }; a = x < 0.25 || (if y > 0.5 { return (0, 0, 1); } else { false }); (a, x > y, false) }");
+    }
+}
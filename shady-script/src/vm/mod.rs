@@ -0,0 +1,477 @@
+use std::collections::HashMap;
+
+use ::{ast, instr};
+use eval::{self, Inputs, Value};
+use functions::lookup_function;
+
+pub use self::batch::{BatchMachine, BATCH};
+
+mod batch;
+
+// Every register holds three lanes. Floats and bools are splatted across all
+// of them so that arithmetic between a float and a vector is just lane-wise,
+// and bools are stored as 1.0 or 0.0.
+type Lanes = [f32; 3];
+type Reg = usize;
+
+// Registers are laid out as the key variables, then constants, then one slot
+// per declared variable, then temporaries.
+const KEYVARS: usize = 5;
+
+#[derive(Debug, Clone)]
+enum Op<R> {
+    Arith(ast::ArithOpKind, R, R, R),
+    Cmp(ast::CmpOpKind, usize, R, R, R),
+    Neg(R, R),
+    Not(R, R),
+    Call(fn(&[Value]) -> Value, R, Vec<(R, instr::Type)>),
+    Vec2(R, R, R),
+    Vec3(R, R, R, R),
+    Move(R, R),
+    Zero(R),
+    If(R, usize),
+    Else(usize),
+    EndIf,
+    Return(R),
+}
+
+impl<R> Op<R> {
+    fn map<S, F: Fn(R) -> S>(self, f: F) -> Op<S> {
+        match self {
+            Op::Arith(op, d, a, b) => Op::Arith(op, f(d), f(a), f(b)),
+            Op::Cmp(op, n, d, a, b) => Op::Cmp(op, n, f(d), f(a), f(b)),
+            Op::Neg(d, a) => Op::Neg(f(d), f(a)),
+            Op::Not(d, a) => Op::Not(f(d), f(a)),
+            Op::Call(func, d, args) => Op::Call(func, f(d), args.into_iter().map(|(r, ty)| (f(r), ty)).collect()),
+            Op::Vec2(d, a, b) => Op::Vec2(f(d), f(a), f(b)),
+            Op::Vec3(d, a, b, c) => Op::Vec3(f(d), f(a), f(b), f(c)),
+            Op::Move(d, s) => Op::Move(f(d), f(s)),
+            Op::Zero(d) => Op::Zero(f(d)),
+            Op::If(c, target) => Op::If(f(c), target),
+            Op::Else(target) => Op::Else(target),
+            Op::EndIf => Op::EndIf,
+            Op::Return(r) => Op::Return(f(r)),
+        }
+    }
+}
+
+// Registers as numbered while compiling, before the constant pool is complete
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Slot {
+    KeyVar(usize),
+    Const(usize),
+    Var(usize),
+    Temp(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Op<Reg>>,
+    init: Vec<Lanes>,
+    ret: instr::Type,
+}
+
+pub struct Machine<'a> {
+    program: &'a Program,
+    regs: Vec<Lanes>,
+}
+
+impl Program {
+    pub fn compile(item: &instr::Item) -> Program {
+        let mut compiler = Compiler {
+            code: Vec::new(),
+            consts: Vec::new(),
+            vars: HashMap::new(),
+            temps: 0,
+            max_temps: 0,
+        };
+
+        compiler.instrs(&item.instrs);
+
+        let consts = KEYVARS;
+        let vars = consts + compiler.consts.len();
+        let temps = vars + compiler.vars.len();
+
+        let code = compiler.code.into_iter().map(|op| op.map(|slot| match slot {
+            Slot::KeyVar(r) => r,
+            Slot::Const(r) => consts + r,
+            Slot::Var(r) => vars + r,
+            Slot::Temp(r) => temps + r,
+        })).collect();
+
+        let mut init = vec![[0.0; 3]; temps + compiler.max_temps];
+        init[consts..vars].copy_from_slice(&compiler.consts);
+
+        Program {
+            code: code,
+            init: init,
+            ret: item.ret,
+        }
+    }
+
+    pub fn machine(&self) -> Machine {
+        Machine {
+            program: self,
+            regs: self.init.clone(),
+        }
+    }
+}
+
+impl<'a> Machine<'a> {
+    pub fn eval(&mut self, inputs: &Inputs) -> Value {
+        let regs = &mut self.regs;
+        let code = &self.program.code;
+
+        regs[0] = [inputs.x; 3];
+        regs[1] = [inputs.y; 3];
+        regs[2] = [inputs.t; 3];
+        regs[3] = [inputs.mx; 3];
+        regs[4] = [inputs.my; 3];
+
+        let mut pc = 0;
+        while pc < code.len() {
+            match code[pc] {
+                Op::Arith(op, d, a, b) => {
+                    let (a, b) = (regs[a], regs[b]);
+                    regs[d] = match op {
+                        ast::ArithOpKind::Add => [a[0] + b[0], a[1] + b[1], a[2] + b[2]],
+                        ast::ArithOpKind::Sub => [a[0] - b[0], a[1] - b[1], a[2] - b[2]],
+                        ast::ArithOpKind::Mul => [a[0] * b[0], a[1] * b[1], a[2] * b[2]],
+                        ast::ArithOpKind::Div => [a[0] / b[0], a[1] / b[1], a[2] / b[2]],
+                        ast::ArithOpKind::Mod => [eval::modulo(a[0], b[0]), eval::modulo(a[1], b[1]), eval::modulo(a[2], b[2])],
+                        ast::ArithOpKind::Pow => [a[0].powf(b[0]), a[1].powf(b[1]), a[2].powf(b[2])],
+                    };
+                },
+
+                Op::Neg(d, a) => {
+                    let a = regs[a];
+                    regs[d] = [-a[0], -a[1], -a[2]];
+                },
+
+                Op::Not(d, a) => regs[d] = from_bool(regs[a][0] == 0.0),
+
+                Op::Cmp(op, n, d, a, b) => {
+                    let (a, b) = (regs[a], regs[b]);
+                    let result = match op {
+                        ast::CmpOpKind::Ne => (0..n).any(|i| a[i] != b[i]),
+                        op => (0..n).all(|i| eval::compare(op, a[i], b[i])),
+                    };
+
+                    regs[d] = from_bool(result);
+                },
+
+                Op::Call(f, d, ref args) => {
+                    // Avoid allocating for the common case of a few arguments
+                    let mut values = [Value::Void; 4];
+                    let result = if args.len() <= values.len() {
+                        for (value, &(r, ty)) in values.iter_mut().zip(args) {
+                            *value = to_value(regs[r], ty);
+                        }
+
+                        f(&values[..args.len()])
+                    } else {
+                        let values: Vec<Value> = args.iter().map(|&(r, ty)| to_value(regs[r], ty)).collect();
+                        f(&values)
+                    };
+
+                    regs[d] = from_value(result);
+                },
+
+                Op::Vec2(d, a, b) => regs[d] = [regs[a][0], regs[b][0], 0.0],
+                Op::Vec3(d, a, b, c) => regs[d] = [regs[a][0], regs[b][0], regs[c][0]],
+                Op::Move(d, s) => regs[d] = regs[s],
+                Op::Zero(d) => regs[d] = [0.0; 3],
+
+                // A false condition skips to the start of the else block,
+                // and finishing the then block skips over it.
+                Op::If(c, target) => if regs[c][0] == 0.0 {
+                    pc = target + 1;
+                    continue
+                },
+
+                Op::Else(target) => {
+                    pc = target;
+                    continue
+                },
+
+                Op::EndIf => (),
+
+                Op::Return(r) => return to_value(regs[r], self.program.ret),
+            }
+
+            pc += 1;
+        }
+
+        // GLSL leaves the result undefined; match the interpreter
+        Value::zero(self.program.ret)
+    }
+}
+
+fn from_bool(b: bool) -> Lanes {
+    if b { [1.0; 3] } else { [0.0; 3] }
+}
+
+fn to_value(lanes: Lanes, ty: instr::Type) -> Value {
+    match ty {
+        instr::Type::Void | instr::Type::Error => Value::Void,
+        instr::Type::Float => Value::Float(lanes[0]),
+        instr::Type::Bool => Value::Bool(lanes[0] != 0.0),
+        instr::Type::Vec2 => Value::Vec2([lanes[0], lanes[1]]),
+        instr::Type::Vec3 => Value::Vec3(lanes),
+    }
+}
+
+fn from_value(value: Value) -> Lanes {
+    match value {
+        Value::Void => [0.0; 3],
+        Value::Float(f) => [f; 3],
+        Value::Bool(b) => from_bool(b),
+        Value::Vec2(v) => [v[0], v[1], 0.0],
+        Value::Vec3(v) => v,
+    }
+}
+
+struct Compiler<'a> {
+    code: Vec<Op<Slot>>,
+    consts: Vec<Lanes>,
+    vars: HashMap<&'a str, (Slot, instr::Type)>,
+    temps: usize,
+    max_temps: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn instrs(&mut self, instrs: &'a [instr::Instr]) {
+        for instr in instrs {
+            // Temporaries never outlive the statement that computes them
+            self.temps = 0;
+
+            match *instr {
+                instr::Instr::Decl(ref name, ty, ref expr) => {
+                    let slot = self.var(name, ty);
+                    match *expr {
+                        Some(ref expr) => {
+                            let (r, _) = self.expr(expr);
+                            self.code.push(Op::Move(slot, r));
+                        },
+
+                        None => self.code.push(Op::Zero(slot)),
+                    }
+                },
+
+                instr::Instr::Assignment(ref name, ref expr) => {
+                    let slot = self.var(name, expr.ty);
+                    let (r, _) = self.expr(&expr.expr);
+                    self.code.push(Op::Move(slot, r));
+                },
+
+                instr::Instr::Return(ref expr) => {
+                    let (r, _) = self.expr(&expr.expr);
+                    self.code.push(Op::Return(r));
+                },
+
+                // Branches stay structured rather than becoming plain jumps so
+                // the batched machine can mask lanes per branch.
+                instr::Instr::ITE(ref cond, ref t, ref e) => {
+                    let (c, _) = self.expr(cond);
+                    let branch = self.code.len();
+                    self.code.push(Op::If(c, 0));
+                    self.instrs(&t.instrs);
+
+                    let els = self.code.len();
+                    self.code.push(Op::Else(0));
+                    if let Some(ref e) = *e {
+                        self.instrs(&e.instrs);
+                    }
+
+                    self.code[branch] = Op::If(c, els);
+                    self.code[els] = Op::Else(self.code.len());
+                    self.code.push(Op::EndIf);
+                },
+            }
+        }
+    }
+
+    fn var(&mut self, name: &'a str, ty: instr::Type) -> Slot {
+        let next = Slot::Var(self.vars.len());
+
+        // Separate branches may each declare a local with the same name but a
+        // different type, so the latest declaration's type wins
+        let var = self.vars.entry(name).or_insert((next, ty));
+        var.1 = ty;
+        var.0
+    }
+
+    fn temp(&mut self) -> Slot {
+        self.temps += 1;
+        if self.temps > self.max_temps {
+            self.max_temps = self.temps;
+        }
+
+        Slot::Temp(self.temps - 1)
+    }
+
+    fn constant(&mut self, lanes: Lanes) -> Slot {
+        match self.consts.iter().position(|c| *c == lanes) {
+            Some(idx) => Slot::Const(idx),
+            None => {
+                self.consts.push(lanes);
+                Slot::Const(self.consts.len() - 1)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &instr::ExprKind) -> (Slot, instr::Type) {
+        match *expr {
+            instr::ExprKind::KeyVar(var) => (Slot::KeyVar(match var {
+                ast::KeyVar::XPos => 0,
+                ast::KeyVar::YPos => 1,
+                ast::KeyVar::Time => 2,
+                ast::KeyVar::MouseX => 3,
+                ast::KeyVar::MouseY => 4,
+            }), instr::Type::Float),
+
+            instr::ExprKind::Literal(lit) => (self.constant([lit as f32; 3]), instr::Type::Float),
+            instr::ExprKind::Bool(b) => (self.constant(from_bool(b)), instr::Type::Bool),
+
+            instr::ExprKind::Var(ref name) => match self.vars.get(&name[..]) {
+                Some(&var) => var,
+                None => panic!("Undefined name {}", name)
+            },
+
+            instr::ExprKind::Application(ref name, ref exprs) => {
+                let args: Vec<(Slot, instr::Type)> = exprs.iter().map(|expr| self.expr(expr)).collect();
+                let tys: Vec<instr::Type> = args.iter().map(|&(_, ty)| ty).collect();
+                let f = match lookup_function(name, &tys) {
+                    Some(f) => f,
+                    None => panic!("No function {} matching {:?}", name, tys)
+                };
+
+                let d = self.temp();
+                self.code.push(Op::Call(f.eval, d, args));
+                (d, f.ret)
+            },
+
+            instr::ExprKind::Vec2(ref exprs) => {
+                let (a, _) = self.expr(&exprs.0);
+                let (b, _) = self.expr(&exprs.1);
+                let d = self.temp();
+                self.code.push(Op::Vec2(d, a, b));
+                (d, instr::Type::Vec2)
+            },
+
+            instr::ExprKind::Vec3(ref exprs) => {
+                let (a, _) = self.expr(&exprs.0);
+                let (b, _) = self.expr(&exprs.1);
+                let (c, _) = self.expr(&exprs.2);
+                let d = self.temp();
+                self.code.push(Op::Vec3(d, a, b, c));
+                (d, instr::Type::Vec3)
+            },
+
+            instr::ExprKind::BinOp(ast::OpKind::ArithOp(op), ref exprs) => {
+                let (a, aty) = self.expr(&exprs.0);
+                let (b, bty) = self.expr(&exprs.1);
+                let d = self.temp();
+                self.code.push(Op::Arith(op, d, a, b));
+                (d, if aty == instr::Type::Float { bty } else { aty })
+            },
+
+            instr::ExprKind::BinOp(ast::OpKind::CmpOp(op), ref exprs) => {
+                let (a, ty) = self.expr(&exprs.0);
+                let (b, _) = self.expr(&exprs.1);
+                let d = self.temp();
+                self.code.push(Op::Cmp(op, lanes(ty), d, a, b));
+                (d, instr::Type::Bool)
+            },
+
+            // The right operand is only evaluated in a branch taken when the
+            // left doesn't decide the result
+            instr::ExprKind::BinOp(ast::OpKind::LogicOp(op), ref exprs) => {
+                let (a, _) = self.expr(&exprs.0);
+                let d = self.temp();
+                self.code.push(Op::Move(d, a));
+
+                let c = match op {
+                    ast::LogicOpKind::And => d,
+                    ast::LogicOpKind::Or => {
+                        let c = self.temp();
+                        self.code.push(Op::Not(c, d));
+                        c
+                    },
+                };
+
+                let branch = self.code.len();
+                self.code.push(Op::If(c, 0));
+
+                let (b, _) = self.expr(&exprs.1);
+                self.code.push(Op::Move(d, b));
+
+                let els = self.code.len();
+                self.code.push(Op::Else(0));
+                self.code[branch] = Op::If(c, els);
+                self.code[els] = Op::Else(self.code.len());
+                self.code.push(Op::EndIf);
+
+                (d, instr::Type::Bool)
+            },
+
+            instr::ExprKind::UnOp(ast::UnOpKind::Neg, ref expr) => {
+                let (a, ty) = self.expr(expr);
+                let d = self.temp();
+                self.code.push(Op::Neg(d, a));
+                (d, ty)
+            },
+
+            instr::ExprKind::UnOp(ast::UnOpKind::Not, ref expr) => {
+                let (a, _) = self.expr(expr);
+                let d = self.temp();
+                self.code.push(Op::Not(d, a));
+                (d, instr::Type::Bool)
+            },
+        }
+    }
+}
+
+fn lanes(ty: instr::Type) -> usize {
+    match ty {
+        instr::Type::Void | instr::Type::Error => 0,
+        instr::Type::Float | instr::Type::Bool => 1,
+        instr::Type::Vec2 => 2,
+        instr::Type::Vec3 => 3,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Program;
+    use eval::Inputs;
+
+    fn check(src: &str) {
+        let sdy = ::parse_input(src).unwrap().analyse().unwrap();
+        let item = sdy.get(0);
+        let program = Program::compile(item);
+        let mut machine = program.machine();
+
+        for i in 0..11 {
+            for j in 0..11 {
+                let inputs = Inputs { x: i as f32 / 10.0, y: j as f32 / 10.0, t: 1.25, mx: 0.3, my: 0.6 };
+                assert_eq!(machine.eval(&inputs), item.eval(&inputs), "{} at {:?}", src, inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn matches_interpreter() {
+        check("image { (x, y, t) }");
+        check("image { c = (1, 2, 3); c * x - y / 2 }");
+        check("image { a = sin(x * 10) + cos(y); b = max(a, mx); (a, b, min(t, my)) }");
+        check("image { c = (0, 0, 0); if x < 0.5 { c = (1, y, 0); } else { return (0, 0, x); }; c }");
+        check("image { if x == y { return (1, 1, 1); }; if x > mx && y > my { return (x, 0, 0); }; (0, y, 0) }");
+        check("image { b = x > y; c = (b, y < 0.5, true); if b == false { return c * 0.5; }; c }");
+        check("image { if x < 0.5 { return (x, x, x); } else { return (y, y, y); }; }");
+        check("image { a = -(x - 0.5) % 0.3; v = -((x, y, t) ^ (y, 2, x)); (a, 2 ^ -y, -a) + v % 0.7 }");
+        check("image { a = x <= 0.5 && (y >= 0.3 || !(x != y)); if a || (x, y) != (0.5, 0.5) && y < 0.2 { return (1, 0, 0); }; (a, x >= y, false) }");
+        check("image { c = if x < 0.5 { (1, 0, 0) } else { d = y * 2; (0, d, 1) }; a = (if y > 0.5 { x } else { if x > 0.3 { return c; } else { 0.5 } }) + 1; c * a }");
+        check("image { if x > 0.5 && (if y < 0.5 { return (1, 0, 0); } else { true }) { return (0, 1, 0); }; a = x < 0.25 || (if y > 0.5 { return (0, 0, 1); } else { false }); (a, x > y, false) }");
+    }
+}
@@ -0,0 +1,40 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("check")
+        .about("Check scripts for errors without rendering them")
+        .arg(Arg::with_name("scripts")
+             .help("The scripts to check")
+             .required(true)
+             .multiple(true))
+        .arg(Arg::with_name("deny-warnings")
+             .help("Treat warnings as errors")
+             .long("deny-warnings")
+             .short("D"))
+}
+
+// Returns whether every script compiled successfully, and without warnings
+// if they are denied
+pub fn check(matches: &ArgMatches) -> bool {
+    let deny = matches.is_present("deny-warnings");
+    let mut ok = true;
+
+    for path in matches.values_of("scripts").unwrap() {
+        match ::compile_linted(path, deny) {
+            Ok((_, warnings)) => {
+                for warning in warnings {
+                    eprintln!("{}", warning);
+                }
+
+                println!("{}: ok", path);
+            },
+
+            Err(err) => {
+                eprintln!("{}", err);
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
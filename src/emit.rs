@@ -0,0 +1,38 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use Error;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("emit")
+        .about("Print a stage of the compiler's output for a script")
+        .arg(Arg::with_name("script")
+             .help("The script to compile")
+             .required(true))
+        .arg(Arg::with_name("stage")
+             .help("The stage to print: the parsed script, the analysed IR or the generated shaders")
+             .long("stage")
+             .takes_value(true)
+             .possible_values(&["ast", "ir", "glsl"])
+             .default_value("glsl"))
+}
+
+pub fn emit(matches: &ArgMatches) -> Result<(), Error> {
+    let path = matches.value_of("script").unwrap();
+
+    match matches.value_of("stage") {
+        Some("ast") => print!("{}", try!(::parse(path))),
+        Some("ir") => print!("{}", try!(::compile(path))),
+
+        _ => {
+            let sdy = try!(::compile(path));
+
+            let mut idx = 0usize;
+            sdy.with_images(|image| {
+                println!("// Image {}\n{}\n", idx, image.standalone_shader());
+                idx += 1;
+            });
+        }
+    }
+
+    Ok(())
+}
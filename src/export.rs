@@ -0,0 +1,350 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use imagefmt::{ColType, ColFmt, png};
+use gif::{self, SetParameter};
+
+use shady_script::Uniform;
+
+use headless::{self, parse_pair, parse_size, parse_fps};
+use Error;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Format {
+    Gif,
+    Apng,
+    Frames,
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("export")
+        .about("Export every image in a script as an animation over a range of t")
+        .arg(Arg::with_name("script")
+             .help("The script to load images from")
+             .required(true))
+        .arg(Arg::with_name("output")
+             .help("The file to write; image n is written to <name>-n.<ext>")
+             .long("output")
+             .short("o")
+             .takes_value(true)
+             .default_value("shady.gif"))
+        .arg(Arg::with_name("format")
+             .help("The output format; guessed from the output extension if not given")
+             .long("format")
+             .takes_value(true)
+             .possible_values(&["gif", "apng", "frames"]))
+        .arg(headless::from_arg())
+        .arg(headless::to_arg())
+        .arg(headless::fps_arg())
+        .arg(headless::size_arg())
+        .arg(headless::mouse_arg())
+        .arg(headless::threads_arg())
+        .arg(headless::tile_size_arg())
+}
+
+pub fn export(matches: &ArgMatches) -> Result<(), Error> {
+    let output = Path::new(matches.value_of("output").unwrap());
+    let (width, height) = parse_size(matches.value_of("size").unwrap()).unwrap();
+    let (mx, my) = parse_pair(matches.value_of("mouse").unwrap()).unwrap();
+    let (from, to) = try!(headless::time_range(matches));
+    let fps = parse_fps(matches.value_of("fps").unwrap()).unwrap();
+    let options = headless::render_options(matches);
+
+    let format = match matches.value_of("format") {
+        Some("gif") => Format::Gif,
+        Some("apng") => Format::Apng,
+        Some(_) => Format::Frames,
+        None => match output.extension().and_then(|ext| ext.to_str()) {
+            Some("gif") => Format::Gif,
+            Some("apng") => Format::Apng,
+            _ => Format::Frames
+        }
+    };
+
+    if format == Format::Gif && (width > 0xffff || height > 0xffff) {
+        return Err(Error::Args(format!("{}x{} is too large for a GIF", width, height)))
+    }
+
+    let frame_count = headless::frame_count(from, to, fps);
+
+    let sdy = try!(::compile(matches.value_of("script").unwrap()));
+
+    let mut idx = 0usize;
+    let mut result = Ok(());
+
+    sdy.with_images(|image| {
+        if result.is_err() {
+            return
+        }
+
+        let frames = if image.standalone_uniforms().contains(&Uniform::Time) {
+            frame_count
+        } else {
+            eprintln!("Warning: image {} does not depend on t; exporting a single frame", idx);
+            1
+        };
+
+        let path = headless::output_path(output, idx);
+        result = Sink::new(format, &path, width, height, fps, frames).and_then(|mut sink| {
+            for frame in 0..frames {
+                let data = image.render_with(&options, width, height, headless::frame_time(from, fps, frame), mx, my);
+                try!(sink.frame(&data));
+            }
+
+            sink.finish()
+        });
+
+        idx += 1;
+    });
+
+    result
+}
+
+enum Sink {
+    Gif(gif::Encoder<File>, u16, u16, u16),
+    Apng(Apng<File>),
+    Frames(PathBuf, usize, usize, usize),
+}
+
+impl Sink {
+    fn new(format: Format, path: &Path, width: usize, height: usize, fps: u16, frames: usize) -> Result<Sink, Error> {
+        match format {
+            Format::Gif => {
+                let file = try!(File::create(path).map_err(Error::IO));
+                let mut encoder = try!(gif::Encoder::new(file, width as u16, height as u16, &[]).map_err(Error::IO));
+                try!(encoder.set(gif::Repeat::Infinite).map_err(Error::IO));
+
+                // GIF delays are in hundredths of a second
+                let delay = (100.0 / fps as f32).round() as u16;
+                Ok(Sink::Gif(encoder, width as u16, height as u16, delay))
+            },
+
+            Format::Apng => {
+                let file = try!(File::create(path).map_err(Error::IO));
+                Apng::new(file, width, height, fps, frames).map(Sink::Apng)
+            },
+
+            Format::Frames => Ok(Sink::Frames(path.to_owned(), width, height, 0)),
+        }
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Result<(), Error> {
+        match *self {
+            Sink::Gif(ref mut encoder, width, height, delay) => {
+                let mut frame = gif::Frame::from_rgb(width, height, data);
+                frame.delay = delay;
+                encoder.write_frame(&frame).map_err(Error::IO)
+            },
+
+            Sink::Apng(ref mut apng) => apng.frame(data),
+
+            Sink::Frames(ref base, width, height, ref mut idx) => {
+                let stem = base.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+                let path = base.with_file_name(format!("{}-{:04}.png", stem, idx));
+                *idx += 1;
+
+                let mut file = try!(File::create(path).map_err(Error::IO));
+                png::write(&mut file, width, height, ColFmt::RGB, data, ColType::Auto, None).map_err(Error::Image)
+            },
+        }
+    }
+
+    fn finish(self) -> Result<(), Error> {
+        match self {
+            Sink::Apng(apng) => apng.finish(),
+            _ => Ok(())
+        }
+    }
+}
+
+// Builds an APNG by encoding each frame as a standalone PNG and moving its
+// image data into the animation's IDAT (first frame) or fdAT chunks.
+struct Apng<W: Write> {
+    w: W,
+    width: usize,
+    height: usize,
+    fps: u16,
+    sequence: u32,
+    first: bool,
+}
+
+impl<W: Write> Apng<W> {
+    fn new(mut w: W, width: usize, height: usize, fps: u16, frames: usize) -> Result<Apng<W>, Error> {
+        // 8 bit RGB, matching what encode_png asks of the encoder
+        let mut ihdr = Vec::new();
+        write_u32(&mut ihdr, width as u32);
+        write_u32(&mut ihdr, height as u32);
+        ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+
+        let mut actl = Vec::new();
+        write_u32(&mut actl, frames as u32);
+        write_u32(&mut actl, 0);
+
+        try!(w.write_all(PNG_SIGNATURE)
+            .and_then(|_| write_chunk(&mut w, b"IHDR", &ihdr))
+            .and_then(|_| write_chunk(&mut w, b"acTL", &actl))
+            .map_err(Error::IO));
+
+        Ok(Apng {
+            w: w,
+            width: width,
+            height: height,
+            fps: fps,
+            sequence: 0,
+            first: true,
+        })
+    }
+
+    fn frame(&mut self, data: &[u8]) -> Result<(), Error> {
+        let png = try!(encode_png(self.width, self.height, data));
+        let idat: Vec<u8> = png_chunks(&png).into_iter()
+            .filter(|&(ty, _)| ty == b"IDAT")
+            .flat_map(|(_, data)| data.iter().cloned())
+            .collect();
+
+        let mut fctl = Vec::new();
+        write_u32(&mut fctl, self.next_sequence());
+        write_u32(&mut fctl, self.width as u32);
+        write_u32(&mut fctl, self.height as u32);
+        write_u32(&mut fctl, 0);
+        write_u32(&mut fctl, 0);
+        fctl.extend_from_slice(&[0, 1]);
+        fctl.extend_from_slice(&[(self.fps >> 8) as u8, self.fps as u8]);
+        fctl.extend_from_slice(&[0, 0]);
+
+        try!(write_chunk(&mut self.w, b"fcTL", &fctl).map_err(Error::IO));
+
+        if self.first {
+            self.first = false;
+            write_chunk(&mut self.w, b"IDAT", &idat).map_err(Error::IO)
+        } else {
+            let mut fdat = Vec::with_capacity(idat.len() + 4);
+            write_u32(&mut fdat, self.next_sequence());
+            fdat.extend_from_slice(&idat);
+            write_chunk(&mut self.w, b"fdAT", &fdat).map_err(Error::IO)
+        }
+    }
+
+    fn finish(mut self) -> Result<(), Error> {
+        write_chunk(&mut self.w, b"IEND", &[]).map_err(Error::IO)
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+}
+
+static PNG_SIGNATURE: &'static [u8] = &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+fn encode_png(width: usize, height: usize, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut png = Vec::new();
+
+    // Every frame must share the IHDR colour type, so never let the encoder pick
+    try!(png::write(&mut png, width, height, ColFmt::RGB, data, ColType::Color, None).map_err(Error::Image));
+    Ok(png)
+}
+
+fn png_chunks(png: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    let mut rest = &png[PNG_SIGNATURE.len()..];
+
+    while rest.len() >= 12 {
+        let len = ((rest[0] as usize) << 24) | ((rest[1] as usize) << 16) | ((rest[2] as usize) << 8) | rest[3] as usize;
+        chunks.push((&rest[4..8], &rest[8..8 + len]));
+        rest = &rest[12 + len..];
+    }
+
+    chunks
+}
+
+fn write_u32(buffer: &mut Vec<u8>, n: u32) {
+    buffer.extend_from_slice(&[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]);
+}
+
+fn write_chunk<W: Write>(w: &mut W, ty: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut buffer = Vec::with_capacity(data.len() + 12);
+    write_u32(&mut buffer, data.len() as u32);
+    buffer.extend_from_slice(ty);
+    buffer.extend_from_slice(data);
+
+    let crc = crc32(&buffer[4..]);
+    write_u32(&mut buffer, crc);
+
+    w.write_all(&buffer)
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+        }
+    }
+
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Apng, crc32, png_chunks, PNG_SIGNATURE};
+
+    fn read_u32(data: &[u8]) -> u32 {
+        ((data[0] as u32) << 24) | ((data[1] as u32) << 16) | ((data[2] as u32) << 8) | data[3] as u32
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn apng_chunks() {
+        let mut out = Vec::new();
+        {
+            let mut apng = Apng::new(&mut out, 2, 1, 25, 3).unwrap();
+            apng.frame(&[0, 0, 0, 255, 255, 255]).unwrap();
+            apng.frame(&[255, 0, 0, 0, 0, 255]).unwrap();
+            apng.frame(&[0, 255, 0, 0, 255, 0]).unwrap();
+            apng.finish().unwrap();
+        }
+
+        assert_eq!(&out[..PNG_SIGNATURE.len()], PNG_SIGNATURE);
+
+        // Every chunk's CRC covers its type and data
+        let mut rest = &out[PNG_SIGNATURE.len()..];
+        while !rest.is_empty() {
+            let len = read_u32(rest) as usize;
+            assert_eq!(read_u32(&rest[8 + len..]), crc32(&rest[4..8 + len]));
+            rest = &rest[12 + len..];
+        }
+
+        let chunks = png_chunks(&out);
+        let types: Vec<&[u8]> = chunks.iter().map(|&(ty, _)| ty).collect();
+        assert_eq!(types, vec![
+            &b"IHDR"[..], b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND",
+        ]);
+
+        // Three frames, looping forever
+        assert_eq!(read_u32(chunks[1].1), 3);
+        assert_eq!(read_u32(&chunks[1].1[4..]), 0);
+
+        // fcTL and fdAT chunks share one sequence, counting up from zero
+        let sequence: Vec<u32> = chunks.iter()
+            .filter(|&&(ty, _)| ty == b"fcTL" || ty == b"fdAT")
+            .map(|&(_, data)| read_u32(data))
+            .collect();
+        assert_eq!(sequence, vec![0, 1, 2, 3, 4]);
+
+        // Each frame lasts 1/25 of a second
+        let fctl = chunks[2].1;
+        assert_eq!(read_u32(&fctl[4..]), 2);
+        assert_eq!(read_u32(&fctl[8..]), 1);
+        assert_eq!(&fctl[20..24], &[0, 1, 0, 25]);
+    }
+}
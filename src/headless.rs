@@ -0,0 +1,257 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+use imagefmt::{ColType, ColFmt, png};
+
+use shady_script::{Image, RenderOptions, Uniform};
+
+use y4m::Y4mWriter;
+use Error;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("render")
+        .about("Render every image in a script to PNG files without opening a window")
+        .arg(Arg::with_name("script")
+             .help("The script to load images from")
+             .required(true))
+        .arg(Arg::with_name("output")
+             .help("The file to write; image n is written to <name>-n.<ext>")
+             .long("output")
+             .short("o")
+             .takes_value(true)
+             .default_value("shady.png"))
+        .arg(size_arg())
+        .arg(Arg::with_name("time")
+             .help("The value of t in seconds")
+             .long("time")
+             .takes_value(true)
+             .default_value("0")
+             .validator(|s| parse_float(&s).map(|_| ())))
+        .arg(mouse_arg())
+        .arg(Arg::with_name("video")
+             .help("Write a Y4M video over --from/--to at --fps instead of PNG stills; - writes to stdout")
+             .long("video")
+             .takes_value(true))
+        .arg(from_arg())
+        .arg(to_arg())
+        .arg(fps_arg())
+        .arg(threads_arg())
+        .arg(tile_size_arg())
+}
+
+pub fn size_arg() -> Arg<'static, 'static> {
+    Arg::with_name("size")
+        .help("The size of the rendered images, as WIDTHxHEIGHT")
+        .long("size")
+        .takes_value(true)
+        .default_value("500x500")
+        .validator(|s| parse_size(&s).map(|_| ()))
+}
+
+pub fn mouse_arg() -> Arg<'static, 'static> {
+    Arg::with_name("mouse")
+        .help("The mouse position as X,Y, each between 0 and 1")
+        .long("mouse")
+        .takes_value(true)
+        .default_value("0,0")
+        .validator(|s| parse_pair(&s).map(|_| ()))
+}
+
+pub fn from_arg() -> Arg<'static, 'static> {
+    Arg::with_name("from")
+        .help("The value of t at the first frame")
+        .long("from")
+        .takes_value(true)
+        .default_value("0")
+        .validator(|s| parse_float(&s).map(|_| ()))
+}
+
+pub fn to_arg() -> Arg<'static, 'static> {
+    Arg::with_name("to")
+        .help("The value of t at which the animation ends")
+        .long("to")
+        .takes_value(true)
+        .default_value("5")
+        .validator(|s| parse_float(&s).map(|_| ()))
+}
+
+pub fn fps_arg() -> Arg<'static, 'static> {
+    Arg::with_name("fps")
+        .help("The number of frames per second")
+        .long("fps")
+        .takes_value(true)
+        .default_value("30")
+        .validator(|s| parse_fps(&s).map(|_| ()))
+}
+
+pub fn threads_arg() -> Arg<'static, 'static> {
+    Arg::with_name("threads")
+        .help("The number of threads to render with; defaults to one per core")
+        .long("threads")
+        .takes_value(true)
+        .validator(|s| parse_positive(&s).map(|_| ()))
+}
+
+pub fn tile_size_arg() -> Arg<'static, 'static> {
+    Arg::with_name("tile-size")
+        .help("The width and height in pixels of the tiles handed to each thread")
+        .long("tile-size")
+        .takes_value(true)
+        .validator(|s| parse_positive(&s).map(|_| ()))
+}
+
+pub fn render_options(matches: &ArgMatches) -> RenderOptions {
+    let mut options = RenderOptions::default();
+
+    if let Some(threads) = matches.value_of("threads") {
+        options.threads = parse_positive(threads).unwrap();
+    }
+
+    if let Some(tile_size) = matches.value_of("tile-size") {
+        options.tile_size = parse_positive(tile_size).unwrap();
+    }
+
+    options
+}
+
+pub fn render(matches: &ArgMatches) -> Result<(), Error> {
+    let output = Path::new(matches.value_of("output").unwrap());
+    let (width, height) = parse_size(matches.value_of("size").unwrap()).unwrap();
+    let time = parse_float(matches.value_of("time").unwrap()).unwrap();
+    let (mx, my) = parse_pair(matches.value_of("mouse").unwrap()).unwrap();
+    let options = render_options(matches);
+
+    let sdy = try!(::compile(matches.value_of("script").unwrap()));
+
+    let mut count = 0usize;
+    sdy.with_images(|_| count += 1);
+
+    let video = matches.value_of("video");
+    if video == Some("-") && count > 1 {
+        return Err(Error::Args(format!("Only one image can be streamed to stdout, but the script has {}", count)))
+    }
+
+    let mut idx = 0usize;
+    let mut result = Ok(());
+
+    sdy.with_images(|image| {
+        if result.is_err() {
+            return
+        }
+
+        result = match video {
+            Some("-") => {
+                let stdout = io::stdout();
+                let lock = stdout.lock();
+                write_video(lock, &image, matches, &options, width, height, mx, my)
+            },
+
+            Some(path) => File::create(output_path(Path::new(path), idx))
+                .map_err(Error::IO)
+                .and_then(|file| write_video(io::BufWriter::new(file), &image, matches, &options, width, height, mx, my)),
+
+            None => {
+                let data = image.render_with(&options, width, height, time, mx, my);
+                write_png(&output_path(output, idx), width, height, &data)
+            }
+        };
+
+        idx += 1;
+    });
+
+    result
+}
+
+fn write_video<W: Write>(w: W, image: &Image, matches: &ArgMatches, options: &RenderOptions, width: usize, height: usize, mx: f32, my: f32) -> Result<(), Error> {
+    let (from, to) = try!(time_range(matches));
+    let fps = parse_fps(matches.value_of("fps").unwrap()).unwrap();
+
+    let animated = image.standalone_uniforms().contains(&Uniform::Time);
+    let mut writer = try!(Y4mWriter::new(w, width, height, fps).map_err(Error::IO));
+    let mut data = Vec::new();
+
+    for frame in 0..frame_count(from, to, fps) {
+        if animated || frame == 0 {
+            data = image.render_with(options, width, height, frame_time(from, fps, frame), mx, my);
+        }
+
+        try!(writer.frame(&data).map_err(Error::IO));
+    }
+
+    writer.finish().map(|_| ()).map_err(Error::IO)
+}
+
+fn write_png(path: &Path, width: usize, height: usize, data: &[u8]) -> Result<(), Error> {
+    let mut file = try!(File::create(path).map_err(Error::IO));
+    png::write(&mut file, width, height, ColFmt::RGB, data, ColType::Auto, None).map_err(Error::Image)
+}
+
+// The values of t an animation runs between, which must go forwards
+pub fn time_range(matches: &ArgMatches) -> Result<(f32, f32), Error> {
+    let from = parse_float(matches.value_of("from").unwrap()).unwrap();
+    let to = parse_float(matches.value_of("to").unwrap()).unwrap();
+
+    if to > from {
+        Ok((from, to))
+    } else {
+        Err(Error::Args(format!("--to ({}) must be later than --from ({})", to, from)))
+    }
+}
+
+// Frames run from `from` up to but not including `to`, so exported loops do
+// not repeat their first frame. Ranges shorter than a frame still get one.
+pub fn frame_count(from: f32, to: f32, fps: u16) -> usize {
+    ((to - from) * fps as f32).round().max(1.0) as usize
+}
+
+pub fn frame_time(from: f32, fps: u16, frame: usize) -> f32 {
+    from + frame as f32 / fps as f32
+}
+
+pub fn output_path(base: &Path, idx: usize) -> PathBuf {
+    let stem = base.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = base.extension().map(|s| s.to_string_lossy().into_owned()).unwrap_or("png".to_owned());
+    base.with_file_name(format!("{}-{}.{}", stem, idx, ext))
+}
+
+pub fn parse_float(s: &str) -> Result<f32, String> {
+    s.trim().parse().map_err(|_| format!("'{}' is not a number", s))
+}
+
+pub fn parse_fps(s: &str) -> Result<u16, String> {
+    match s.trim().parse() {
+        Ok(fps) if fps > 0 => Ok(fps),
+        _ => Err(format!("'{}' is not a positive whole number of frames per second", s))
+    }
+}
+
+pub fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.trim().parse() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("'{}' is not a positive whole number", s))
+    }
+}
+
+pub fn parse_size(s: &str) -> Result<(usize, usize), String> {
+    let err = || format!("'{}' is not a size of the form WIDTHxHEIGHT", s);
+
+    let mut parts = s.split('x');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(w), Some(h), None) => match (w.trim().parse(), h.trim().parse()) {
+            (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+            _ => Err(err())
+        },
+
+        _ => Err(err())
+    }
+}
+
+pub fn parse_pair(s: &str) -> Result<(f32, f32), String> {
+    let mut parts = s.split(',');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(a), Some(b), None) => Ok((try!(parse_float(a)), try!(parse_float(b)))),
+        _ => Err(format!("'{}' is not a pair of the form X,Y", s))
+    }
+}
@@ -1,10 +1,11 @@
-#![feature(type_ascription, slice_patterns)]
+#![feature(type_ascription)]
 
 #[macro_use] extern crate glium;
 extern crate clap;
 extern crate notify;
 extern crate shady_script;
 extern crate imagefmt;
+extern crate gif;
 
 #[cfg(target_os="macos")]
 extern crate cocoa;
@@ -12,28 +13,35 @@ extern crate cocoa;
 #[cfg(target_os="macos")]
 #[macro_use] extern crate objc;
 
+use std::fmt;
 use std::fs::File;
 use std::path::Path;
 use std::io::Read;
-use std::sync::mpsc::channel;
 use std::time::Instant;
 
 use imagefmt::{ColType, ColFmt, png};
 
 use glium::{Program, VertexBuffer, Surface};
-use glium::glutin::{WindowId, EventsLoop, Event, WindowEvent};
+use glium::glutin::{WindowId, EventsLoop, Event, WindowEvent, KeyboardInput, ElementState, VirtualKeyCode};
 use glium::texture::RawImage2d;
 use glium::texture::texture2d::Texture2d;
 use glium::backend::glutin::Display;
-use glium::uniforms::EmptyUniforms;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches};
 
-use notify::{RecommendedWatcher, Watcher, RecursiveMode};
+use shady_script::{Shady, CompileError};
+use shady_script::ast::AST;
 
-use shady_script::{ParseError, AnalyseError, Uniform};
+use watch::ScriptWatcher;
 
 mod platform;
+mod headless;
+mod export;
+mod y4m;
+mod term;
+mod emit;
+mod check;
+mod watch;
 
 #[derive(Copy, Clone)]
 struct Vertex {
@@ -72,78 +80,138 @@ struct ImageDisplay {
     id: WindowId,
     buffer: VertexBuffer<Vertex>,
     program: Program,
-    uniforms: Vec<Uniform>,
     mouse_position: (i32, i32),
     done: bool,
+    // Drawing failed, so wait for a reload before trying again
+    broken: bool,
 }
 
 #[derive(Debug)]
-enum Error<'a> {
+enum Error {
     IO(std::io::Error),
-    Parse(ParseError<'a>),
-    Analyse(AnalyseError),
+    Compile(CompileError),
+    Image(imagefmt::Error),
+    Args(String),
+    // From the window system or the GPU, such as a shader that won't compile
+    Graphics(String),
+    Watch(notify::Error),
 }
 
-fn load_images<'a, P: AsRef<Path>>(buffer: &'a mut String, event_loop: &EventsLoop, displays: &mut Vec<ImageDisplay>, path: P) -> Result<(), Error<'a>> {
-    buffer.clear();
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::IO(ref err) => write!(f, "error: {}", err),
+            Error::Compile(ref err) => write!(f, "{}", err),
+            Error::Image(ref err) => write!(f, "error: {:?}", err),
+            Error::Args(ref msg) => write!(f, "error: {}", msg),
+            Error::Graphics(ref msg) => write!(f, "error: {}", msg),
+            Error::Watch(ref err) => write!(f, "error: cannot watch the script for changes: {}", err),
+        }
+    }
+}
 
-    let mut idx = 0usize;
+fn read<P: AsRef<Path>>(path: P) -> Result<String, Error> {
+    let mut source = String::new();
 
-    if let Err(err) = File::open(path).and_then(|mut file| file.read_to_string(buffer)) {
-        return Err(Error::IO(err))
+    match File::open(path).and_then(|mut file| file.read_to_string(&mut source)) {
+        Ok(_) => Ok(source),
+        Err(err) => Err(Error::IO(err)),
     }
+}
 
-    let ast = match shady_script::parse_input(buffer) {
-        Ok(ast) => ast,
-        Err(err) => return Err(Error::Parse(err))
-    };
+fn parse<P: AsRef<Path>>(path: P) -> Result<AST, Error> {
+    let source = try!(read(&path));
 
-    let sdy = match ast.analyse() {
-        Ok(sdy) => sdy,
-        Err(err) => return Err(Error::Analyse(err))
-    };
+    shady_script::parse_input(&source).map_err(|errors| {
+        Error::Compile(CompileError::from_parse(&source, errors).with_file(path.as_ref().display().to_string()))
+    })
+}
 
-    sdy.with_images(|image| {
-        let shader = image.standalone_shader();
-        println!("\nGenerated Shader {}:\n{}\n", idx, shader);
+// Compiles a script, printing any warnings about it
+fn compile<P: AsRef<Path>>(path: P) -> Result<Shady, Error> {
+    let (sdy, warnings) = try!(compile_linted(path, false));
 
-        let new_display = match displays.get_mut(idx) {
-            Some(mut display) => {
-                display.display.gl_window().window().set_title(&format!("Shady Image {}", idx));
-                display.program = Program::from_source(&display.display, vertex_shader_source, &shader, None).unwrap();
-                display.uniforms = image.standalone_uniforms();
-                None
-            }
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+
+    Ok(sdy)
+}
+
+// Compiles a script, returning its warnings rendered for display. If `deny`
+// is set, any warnings fail compilation instead.
+fn compile_linted<P: AsRef<Path>>(path: P, deny: bool) -> Result<(Shady, Vec<String>), Error> {
+    let source = try!(read(&path));
+    let name = path.as_ref().display().to_string();
+
+    match shady_script::compile_with_warnings(&source) {
+        Ok((_, ref warnings)) if deny && !warnings.is_empty() =>
+            Err(Error::Compile(CompileError::from_warnings(&source, warnings.clone()).with_file(name))),
+
+        Ok((sdy, warnings)) => {
+            let rendered = warnings.iter().map(|warning| warning.diagnostic().render(&name, &source)).collect();
+            Ok((sdy, rendered))
+        },
+
+        Err(err) => Err(Error::Compile(err.with_file(name))),
+    }
+}
+
+// Opens a window for each image in the script, or reuses the existing ones. If
+// any shader fails to build, every window keeps its previous program, and the
+// new windows are closed before ever being shown.
+fn load_images<P: AsRef<Path>>(event_loop: &EventsLoop, displays: &mut Vec<ImageDisplay>, path: P) -> Result<(), Error> {
+    let sdy = try!(compile(path));
+
+    let mut shaders = Vec::new();
+    sdy.with_images(|image| shaders.push(image.standalone_shader()));
+
+    let mut programs = Vec::new();
+    let mut opened = Vec::new();
+
+    for (idx, shader) in shaders.iter().enumerate() {
+        match displays.get(idx) {
+            Some(display) => programs.push(try!(build_program(&display.display, shader))),
 
             None => {
-                let display = platform::open_window(event_loop, &format!("Shady Image {}", idx), (500, 500));
-                let vertex_buffer = glium::VertexBuffer::new(&display, &shape).unwrap();
-                let program = Program::from_source(&display, vertex_shader_source, &shader, None).unwrap();
+                let display = try!(platform::open_window(event_loop, &format!("Shady Image {}", idx), (500, 500))
+                    .map_err(|err| Error::Graphics(format!("cannot open a window: {:?}", err))));
+                let vertex_buffer = try!(VertexBuffer::new(&display, &shape).map_err(|err| Error::Graphics(format!("{:?}", err))));
+                let program = try!(build_program(&display, shader));
 
                 let id = display.gl_window().window().id();
 
-                Some(ImageDisplay {
+                opened.push(ImageDisplay {
                     display: display,
                     id: id,
                     buffer: vertex_buffer,
                     program: program,
-                    uniforms: image.standalone_uniforms(),
                     mouse_position: (0, 0),
                     done: false,
-                })
+                    broken: false,
+                });
             }
-        };
-
-        if let Some(display) = new_display {
-            displays.push(display)
         }
+    }
+
+    for (idx, (display, program)) in displays.iter_mut().zip(programs).enumerate() {
+        display.display.gl_window().window().set_title(&format!("Shady Image {}", idx));
+        display.program = program;
+        display.broken = false;
+    }
 
-        idx += 1;
-    });
+    for display in &opened {
+        display.display.gl_window().window().show();
+    }
 
+    displays.extend(opened);
     Ok(())
 }
 
+fn build_program(display: &Display, shader: &str) -> Result<Program, Error> {
+    Program::from_source(display, vertex_shader_source, shader, None).map_err(|err| Error::Graphics(err.to_string()))
+}
+
 fn with_display<F: FnMut(&mut ImageDisplay)>(displays: &mut [ImageDisplay], id: WindowId, mut f: F) {
     for display in displays {
         if display.id == id {
@@ -157,6 +225,7 @@ fn main() {
     let matches = App::new("Shady")
         .author("Samuel Sleight <samuel.sleight@gmail.com>")
         .version("0.1.0")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("script")
              .help("The script to load images from")
              .required(true))
@@ -168,42 +237,90 @@ fn main() {
              .help("Keep watching the script if all windows are closed")
              .long("keep")
              .short("k"))
+        .arg(Arg::with_name("reset")
+             .help("Restart the clock whenever the script is reloaded; press R in a window to restart it at any time")
+             .long("reset")
+             .short("r"))
+        .subcommand(headless::subcommand())
+        .subcommand(export::subcommand())
+        .subcommand(term::subcommand())
+        .subcommand(emit::subcommand())
+        .subcommand(check::subcommand())
         .get_matches();
 
+    match matches.subcommand() {
+        ("render", Some(matches)) => {
+            if let Err(err) = headless::render(matches) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+
+        ("export", Some(matches)) => {
+            if let Err(err) = export::export(matches) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+
+        ("term", Some(matches)) => term::term(matches),
+
+        ("emit", Some(matches)) => {
+            if let Err(err) = emit::emit(matches) {
+                eprintln!("{}", err);
+                std::process::exit(1);
+            }
+        },
+
+        ("check", Some(matches)) => if !check::check(matches) {
+            std::process::exit(1);
+        },
+
+        _ => view(&matches)
+    }
+}
+
+fn view(matches: &ArgMatches) {
     let path = Path::new(matches.value_of("script").unwrap());
     let once = matches.is_present("once");
     let keep = !once && matches.is_present("keep");
+    let reset = !once && matches.is_present("reset");
 
-    let mut buffer = String::new();
     let mut displays = Vec::new();
     let mut event_loop = EventsLoop::new();
 
-    if let Err(err) = load_images(&mut buffer, &event_loop, &mut displays, path) {
-        println!("{:?}", err);
+    if let Err(err) = load_images(&event_loop, &mut displays, path) {
+        eprintln!("{}", err);
     }
 
-    let watcher = if once {
+    // Without a watcher the images are still shown, just never reloaded
+    let mut watcher = if once {
         None
     } else {
-        let (tx, rx) = channel();
-        let mut watcher: RecommendedWatcher = Watcher::new_raw(tx).unwrap();
-        watcher.watch(path, RecursiveMode::Recursive).unwrap();
-        Some((rx, watcher))
+        match ScriptWatcher::new(path) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                eprintln!("{}", Error::Watch(err));
+                None
+            }
+        }
     };
 
     let mut time = Instant::now();
     loop {
-        if let Some((ref rx, _)) = watcher {
-            if let Ok(_) = rx.try_recv() {
-                time = Instant::now();
-
-                if let Err(err) = load_images(&mut buffer, &event_loop, &mut displays, path) {
-                    println!("{:?}", err);
-                }
-            };
-        };
+        if watcher.as_mut().map_or(false, |watcher| watcher.changed()) {
+            // On failure the windows keep showing the last good script
+            match load_images(&event_loop, &mut displays, path) {
+                Ok(()) => if reset {
+                    time = Instant::now();
+                },
+
+                Err(err) => eprintln!("{}", err),
+            }
+        }
 
-        let duration = time.elapsed().subsec_nanos() as f32 / 1000000000.0;
+        let elapsed = time.elapsed();
+        let duration = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1000000000.0;
 
         event_loop.poll_events(|event| {
             if let Event::WindowEvent { event: e, window_id: id } = event {
@@ -211,6 +328,10 @@ fn main() {
                     WindowEvent::Closed => with_display(&mut displays, id, |display| display.done = true),
                     WindowEvent::MouseMoved { position, .. } => with_display(&mut displays, id, |display| display.mouse_position = (position.0 as i32, position.1 as i32)),
 
+                    WindowEvent::KeyboardInput {
+                        input: KeyboardInput { state: ElementState::Pressed, virtual_keycode: Some(VirtualKeyCode::R), .. }, ..
+                    } => time = Instant::now(),
+
                     _ => ()
                 }
             }
@@ -226,7 +347,6 @@ fn main() {
                         &mut target, 
                         &display.program, 
                         &display.buffer, 
-                        &display.uniforms, 
                         duration, 
                         display.mouse_position.0 as f32 / size.0 as f32, 
                         display.mouse_position.1 as f32 / size.1 as f32
@@ -239,21 +359,34 @@ fn main() {
             }
             */
 
-            let size = display.display.gl_window().window().get_inner_size_pixels().unwrap();
+            if display.broken {
+                continue
+            }
+
+            // There's no size once the window has been closed
+            let size = match display.display.gl_window().window().get_inner_size_pixels() {
+                Some(size) => size,
+                None => continue,
+            };
+
             let mut target = display.display.draw();
 
-            render(
+            let drawn = render(
                 &mut target, 
                 &display.program, 
                 &display.buffer, 
-                &display.uniforms, 
                 duration, 
                 display.mouse_position.0 as f32 / size.0 as f32, 
                 display.mouse_position.1 as f32 / size.1 as f32
             );
 
-            target.finish().unwrap();
+            // The frame has to be finished even if drawing it failed
+            let finished = target.finish().map_err(|err| Error::Graphics(format!("{:?}", err)));
 
+            if let Err(err) = drawn.and(finished) {
+                eprintln!("{}", err);
+                display.broken = true;
+            }
         }
 
         displays.retain(|display| !display.done);
@@ -263,55 +396,21 @@ fn main() {
     }
 }
 
-fn render<S: Surface>(surface: &mut S, program: &Program, buffer: &VertexBuffer<Vertex>, uniforms: &[Uniform], time: f32, mx: f32, my: f32) {
+// Every input is passed, since glium ignores the uniforms a shader doesn't use
+fn render<S: Surface>(surface: &mut S, program: &Program, buffer: &VertexBuffer<Vertex>, time: f32, mx: f32, my: f32) -> Result<(), Error> {
     surface.clear_color(0.0, 0.0, 0.0, 0.0);
 
-    macro_rules! render {
-        ($uniforms:expr) => (surface.draw(
-            buffer, 
-            &glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), 
-            program, 
-            &$uniforms, 
-            &Default::default()
-        ).unwrap())
+    let uniforms = uniform! {
+        time: time,
+        mouse_x: mx,
+        mouse_y: my,
     };
 
-    match uniforms {
-        &[] => render!(EmptyUniforms),
-
-        &[Uniform::Time] => render!(uniform! {
-            time: time
-        }),
-
-        &[Uniform::MouseX] => render!(uniform! {
-            mouse_x: mx
-        }),
-
-        &[Uniform::MouseY] => render!(uniform! {
-            mouse_y: my
-        }),
-
-        &[Uniform::Time, Uniform::MouseX] => render!(uniform! {
-            time: time,
-            mouse_x: mx
-        }),
-
-        &[Uniform::Time, Uniform::MouseY] => render!(uniform! {
-            time: time,
-            mouse_y: my
-        }),
-
-        &[Uniform::MouseX, Uniform::MouseY] => render!(uniform! {
-            mouse_x: mx,
-            mouse_y: my
-        }),
-
-        &[Uniform::Time, Uniform::MouseX, Uniform::MouseY] => render!(uniform! {
-            time: time,
-            mouse_x: mx,
-            mouse_y: my,
-        }),
-
-        _ => panic!("Unexpected uniform format - this shouldn't happen")
-    };
+    surface.draw(
+        buffer, 
+        &glium::index::NoIndices(glium::index::PrimitiveType::TriangleFan), 
+        program, 
+        &uniforms, 
+        &Default::default()
+    ).map_err(|err| Error::Graphics(err.to_string()))
 }
@@ -2,7 +2,7 @@ use std::ffi::CStr;
 use std::path::Path;
 
 use glium::glutin::{EventsLoop, ContextBuilder, WindowBuilder};
-use glium::backend::glutin::Display;
+use glium::backend::glutin::{Display, DisplayCreationError};
 use glium::glutin::os::macos::{ActivationPolicy, WindowBuilderExt, WindowExt};
 
 use cocoa::base::id;
@@ -10,16 +10,17 @@ use cocoa::foundation::{NSString, NSArray, NSUInteger};
 use cocoa::appkit::{NSApp, NSApplication, NSMenu, NSWindow};
 use objc::runtime::{Class, NO};
 
-pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) -> Display {
-    let display = Display::new(
+// Windows start hidden, and are shown once there is something to draw
+pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) -> Result<Display, DisplayCreationError> {
+    let display = try!(Display::new(
         WindowBuilder::new()
             .with_title(title)
-            .with_dimensions(w, h),
+            .with_dimensions(w, h)
+            .with_visibility(false),
             // .with_activation_policy(ActivationPolicy::Accessory)
         ContextBuilder::new(),
         event_loop
-    )
-        .unwrap();
+    ));
 
     unsafe {
         let app = (display.gl_window().window().get_nswindow() as id).windowController();
@@ -29,7 +30,7 @@ pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) ->
         // app.setMainMenu_(menu);
     }
 
-    display
+    Ok(display)
 }
 
 pub fn save_image<F: FnMut(&Path)>(mut f: F) {
@@ -9,18 +9,18 @@ mod platform {
     use std::path::Path;
 
     use glium::glutin::{EventsLoop, ContextBuilder, WindowBuilder};
-    use glium::backend::glutin::Display;
+    use glium::backend::glutin::{Display, DisplayCreationError};
 
-    pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) -> Display {
+    // Windows start hidden, and are shown once there is something to draw
+    pub fn open_window(event_loop: &EventsLoop, title: &str, (w, h): (u32, u32)) -> Result<Display, DisplayCreationError> {
         Display::new(
-            glium::glutin::WindowBuilder::new()
+            WindowBuilder::new()
                 .with_title(title)
-                .with_dimensions(w, h),
+                .with_dimensions(w, h)
+                .with_visibility(false),
             ContextBuilder::new(),
             event_loop
         )
-            .unwrap();
-
     }
 
     pub fn save_image<F: FnMut(Path)>(_: F) {}
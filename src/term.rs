@@ -0,0 +1,307 @@
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use shady_script::{Shady, Uniform};
+
+use headless::{self, parse_fps, parse_pair, parse_size};
+use watch::ScriptWatcher;
+use Error;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mode {
+    Truecolor,
+    Sixel,
+}
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("term")
+        .about("Preview every image in a script in the terminal, reloading it on change")
+        .arg(Arg::with_name("script")
+             .help("The script to load images from")
+             .required(true))
+        .arg(Arg::with_name("once")
+             .help("Only load images once; do not watch the script for changes")
+             .long("once")
+             .short("o"))
+        .arg(Arg::with_name("reset")
+             .help("Restart the clock whenever the script is reloaded")
+             .long("reset")
+             .short("r"))
+        .arg(Arg::with_name("mode")
+             .help("How to draw images; sixel is used automatically if $TERM advertises it")
+             .long("mode")
+             .takes_value(true)
+             .possible_values(&["auto", "truecolor", "sixel"])
+             .default_value("auto"))
+        .arg(Arg::with_name("size")
+             .help("The size of each image in character cells, as COLUMNSxROWS; defaults to fit the terminal")
+             .long("size")
+             .takes_value(true)
+             .validator(|s| parse_size(&s).map(|_| ())))
+        .arg(Arg::with_name("fps")
+             .help("The maximum number of frames per second for images that use t")
+             .long("fps")
+             .takes_value(true)
+             .default_value("15")
+             .validator(|s| parse_fps(&s).map(|_| ())))
+        .arg(headless::mouse_arg())
+        .arg(headless::threads_arg())
+        .arg(headless::tile_size_arg())
+}
+
+pub fn term(matches: &ArgMatches) {
+    let path = Path::new(matches.value_of("script").unwrap());
+    let (mx, my) = parse_pair(matches.value_of("mouse").unwrap()).unwrap();
+    let fps = parse_fps(matches.value_of("fps").unwrap()).unwrap();
+    let options = headless::render_options(matches);
+
+    let mode = match matches.value_of("mode") {
+        Some("truecolor") => Mode::Truecolor,
+        Some("sixel") => Mode::Sixel,
+        _ => match env::var("TERM") {
+            Ok(ref term) if term.contains("sixel") || term == "mlterm" || term == "yaft-256color" => Mode::Sixel,
+            _ => Mode::Truecolor
+        }
+    };
+
+    let once = matches.is_present("once");
+    let reset = !once && matches.is_present("reset");
+
+    let mut shady = None;
+    let mut error = None;
+
+    reload(path, &mut shady, &mut error);
+
+    // Without a watcher the images are still shown, just never reloaded
+    let mut watch_error = None;
+    let mut watcher = if once {
+        None
+    } else {
+        match ScriptWatcher::new(path) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                watch_error = Some(Error::Watch(err).to_string());
+                None
+            }
+        }
+    };
+
+    let frame_time = Duration::from_millis(1000 / fps as u64);
+    let mut time = Instant::now();
+    let mut dirty = true;
+
+    print!("\x1b[2J");
+
+    loop {
+        let frame_start = Instant::now();
+
+        if watcher.as_mut().map_or(false, |watcher| watcher.changed()) {
+            if reload(path, &mut shady, &mut error) && reset {
+                time = Instant::now();
+            }
+
+            dirty = true;
+
+            print!("\x1b[2J");
+        }
+
+        let animated = shady.as_ref().map_or(false, |sdy: &Shady| {
+            let mut animated = false;
+            sdy.with_images(|image| animated |= image.standalone_uniforms().contains(&Uniform::Time));
+            animated
+        });
+
+        if dirty || animated {
+            let elapsed = time.elapsed();
+            let t = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1000000000.0;
+
+            let mut out = String::from("\x1b[H");
+
+            if let Some(ref sdy) = shady {
+                let mut count = 0usize;
+                sdy.with_images(|_| count += 1);
+
+                let (cols, rows) = match matches.value_of("size") {
+                    Some(size) => parse_size(size).unwrap(),
+                    None => terminal_size(count),
+                };
+
+                sdy.with_images(|image| match mode {
+                    Mode::Truecolor => {
+                        let data = image.render_with(&options, cols, rows * 2, t, mx, my);
+                        out.push_str(&truecolor(&data, cols, rows * 2));
+                    },
+
+                    Mode::Sixel => {
+                        // Assume the common cell size of roughly 10x20 pixels
+                        let (width, height) = (cols * 10, rows * 20);
+                        let data = image.render_with(&options, width, height, t, mx, my);
+                        out.push_str(&sixel(&data, width, height));
+                        out.push_str("\r\n");
+                    },
+                });
+            }
+
+            if let Some(ref err) = error {
+                let _ = writeln!(out, "\x1b[0J{}", err);
+            }
+
+            if let Some(ref err) = watch_error {
+                let _ = writeln!(out, "\x1b[0J{}", err);
+            }
+
+            let stdout = io::stdout();
+            let mut lock = stdout.lock();
+            let _ = lock.write_all(out.as_bytes()).and_then(|_| lock.flush());
+
+            dirty = false;
+        }
+
+        if watcher.is_none() && !animated {
+            break
+        }
+
+        let elapsed = frame_start.elapsed();
+        if elapsed < frame_time {
+            thread::sleep(frame_time - elapsed);
+        }
+    }
+}
+
+// Keeps the last successfully compiled script if the new one has errors, and
+// returns whether it compiled. Warnings are shown in the same place as errors.
+fn reload(path: &Path, shady: &mut Option<Shady>, error: &mut Option<String>) -> bool {
+    match ::compile_linted(path, false) {
+        Ok((sdy, warnings)) => {
+            *shady = Some(sdy);
+            *error = if warnings.is_empty() { None } else { Some(warnings.join("\n")) };
+            true
+        },
+
+        Err(err) => {
+            *error = Some(err.to_string());
+            false
+        }
+    }
+}
+
+// Splits the terminal, as reported by $COLUMNS and $LINES, between the
+// images, leaving a line free for errors.
+fn terminal_size(images: usize) -> (usize, usize) {
+    let var = |name, default| env::var(name).ok().and_then(|s| s.parse().ok()).unwrap_or(default);
+    let (cols, lines): (usize, usize) = (var("COLUMNS", 80), var("LINES", 24));
+
+    (cols, ((lines.saturating_sub(1)) / images.max(1)).max(1))
+}
+
+// Draws two pixels per character cell using the upper half block, with the
+// top pixel as the foreground colour and the bottom pixel as the background.
+pub fn truecolor(data: &[u8], width: usize, height: usize) -> String {
+    let mut out = String::new();
+
+    for row in (0..height).filter(|row| row % 2 == 0) {
+        for col in 0..width {
+            let top = &data[(row * width + col) * 3..][..3];
+            let _ = write!(out, "\x1b[38;2;{};{};{}m", top[0], top[1], top[2]);
+
+            if row + 1 < height {
+                let bottom = &data[((row + 1) * width + col) * 3..][..3];
+                let _ = write!(out, "\x1b[48;2;{};{};{}m", bottom[0], bottom[1], bottom[2]);
+            }
+
+            out.push('\u{2580}');
+        }
+
+        out.push_str("\x1b[0m\r\n");
+    }
+
+    out
+}
+
+// Encodes the image as sixels using a fixed 6x6x6 colour cube palette.
+pub fn sixel(data: &[u8], width: usize, height: usize) -> String {
+    let level = |c: u8| (c as usize * 5 + 127) / 255;
+    let indices: Vec<usize> = data.chunks(3).map(|px| level(px[0]) * 36 + level(px[1]) * 6 + level(px[2])).collect();
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", width, height);
+    for idx in 0..216 {
+        let percent = |l: usize| l * 100 / 5;
+        let _ = write!(out, "#{};2;{};{};{}", idx, percent(idx / 36), percent(idx / 6 % 6), percent(idx % 6));
+    }
+
+    for band in (0..height).filter(|row| row % 6 == 0) {
+        let rows = band..(band + 6).min(height);
+
+        let mut used = [false; 216];
+        for row in rows.clone() {
+            for col in 0..width {
+                used[indices[row * width + col]] = true;
+            }
+        }
+
+        for colour in (0..216).filter(|&c| used[c]) {
+            let _ = write!(out, "#{}", colour);
+
+            let mut run = (0u8, 0usize);
+            for col in 0..width {
+                let mut bits = 0u8;
+                for row in rows.clone() {
+                    if indices[row * width + col] == colour {
+                        bits |= 1 << (row - band);
+                    }
+                }
+
+                if bits == run.0 {
+                    run.1 += 1;
+                } else {
+                    push_run(&mut out, run);
+                    run = (bits, 1);
+                }
+            }
+
+            push_run(&mut out, run);
+            out.push('$');
+        }
+
+        out.push('-');
+    }
+
+    out.push_str("\x1b\\");
+    out
+}
+
+fn push_run(out: &mut String, (bits, count): (u8, usize)) {
+    let c = (63 + bits) as char;
+
+    match count {
+        0 => (),
+        1..=3 => for _ in 0..count { out.push(c) },
+        _ => { let _ = write!(out, "!{}{}", count, c); },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sixel, truecolor};
+
+    #[test]
+    fn half_blocks() {
+        let data = [255, 0, 0, 0, 255, 0, 0, 0, 255];
+        assert_eq!(truecolor(&data, 1, 3), "\x1b[38;2;255;0;0m\x1b[48;2;0;255;0m\u{2580}\x1b[0m\r\n\x1b[38;2;0;0;255m\u{2580}\x1b[0m\r\n");
+    }
+
+    #[test]
+    fn sixel_bands() {
+        let data = vec![255; 4 * 7 * 3];
+        let out = sixel(&data, 4, 7);
+
+        assert!(out.starts_with("\x1bPq\"1;1;4;7#0;2;0;0;0"));
+        assert!(out.ends_with("#215!4~$-#215!4@$-\x1b\\"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver};
+use std::time::{Duration, Instant};
+
+use notify::{self, RecommendedWatcher, Watcher, RecursiveMode, RawEvent};
+
+// Watches a script for changes. The script's directory is watched rather than
+// the script itself, since editors often save by replacing the file, which
+// would end a watch on it.
+pub struct ScriptWatcher {
+    path: PathBuf,
+    rx: Receiver<RawEvent>,
+    changed: Option<Instant>,
+    _watcher: RecommendedWatcher,
+}
+
+impl ScriptWatcher {
+    pub fn new(path: &Path) -> notify::Result<ScriptWatcher> {
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        };
+
+        let (tx, rx) = channel();
+        let mut watcher: RecommendedWatcher = try!(Watcher::new_raw(tx));
+        try!(watcher.watch(dir, RecursiveMode::NonRecursive));
+
+        Ok(ScriptWatcher {
+            path: path.to_owned(),
+            rx: rx,
+            changed: None,
+            _watcher: watcher,
+        })
+    }
+
+    // Whether the script should be reloaded. Editors can write a file several
+    // times for one save, so this waits for the changes to settle.
+    pub fn changed(&mut self) -> bool {
+        while let Ok(event) = self.rx.try_recv() {
+            if self.concerns(&event) {
+                self.changed = Some(Instant::now());
+            }
+        }
+
+        match self.changed {
+            Some(at) if at.elapsed() >= Duration::from_millis(100) => {
+                self.changed = None;
+                true
+            },
+
+            _ => false,
+        }
+    }
+
+    // Whether an event is about the script, rather than another file in its
+    // directory
+    fn concerns(&self, event: &RawEvent) -> bool {
+        event.path.as_ref().map_or(false, |changed| changed.file_name() == self.path.file_name())
+    }
+}
@@ -0,0 +1,70 @@
+use std::io::{self, Write};
+
+// Writes uncompressed YUV4MPEG2 video. Frames are stored as full resolution
+// 4:4:4 BT.601 so no chroma subsampling is needed.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    width: usize,
+    height: usize,
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut w: W, width: usize, height: usize, fps: u16) -> io::Result<Y4mWriter<W>> {
+        try!(writeln!(w, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", width, height, fps));
+
+        Ok(Y4mWriter {
+            w: w,
+            width: width,
+            height: height,
+            planes: vec![0; width * height * 3],
+        })
+    }
+
+    // Takes a row-major, top-down RGB8 frame, as produced by Image::render
+    pub fn frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let size = self.width * self.height;
+
+        for (idx, px) in rgb.chunks(3).take(size).enumerate() {
+            let (r, g, b) = (px[0] as f32 / 255.0, px[1] as f32 / 255.0, px[2] as f32 / 255.0);
+
+            self.planes[idx] = to_u8(16.0 + 65.481 * r + 128.553 * g + 24.966 * b);
+            self.planes[size + idx] = to_u8(128.0 - 37.797 * r - 74.203 * g + 112.0 * b);
+            self.planes[2 * size + idx] = to_u8(128.0 + 112.0 * r - 93.786 * g - 18.214 * b);
+        }
+
+        try!(self.w.write_all(b"FRAME\n"));
+        self.w.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        try!(self.w.flush());
+        Ok(self.w)
+    }
+}
+
+fn to_u8(c: f32) -> u8 {
+    c.round().max(0.0).min(255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Y4mWriter;
+
+    #[test]
+    fn header_and_frames() {
+        let mut writer = Y4mWriter::new(Vec::new(), 2, 1, 25).unwrap();
+        writer.frame(&[0, 0, 0, 255, 255, 255]).unwrap();
+        writer.frame(&[255, 0, 0, 0, 0, 255]).unwrap();
+        let data = writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F25:1 Ip A1:1 C444\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+
+        let frames = &data[header.len()..];
+        assert_eq!(frames.len(), 2 * (6 + 2 * 3));
+        assert_eq!(&frames[..6], b"FRAME\n");
+        assert_eq!(&frames[6..12], &[16, 235, 128, 128, 128, 128]);
+        assert_eq!(&frames[12..18], b"FRAME\n");
+    }
+}